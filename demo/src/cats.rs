@@ -4,6 +4,7 @@ use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::{Html, IntoResponse, Response};
 use bytes::Bytes;
+use layer::error::ErrorReport;
 use serde::Deserialize;
 use thiserror::Error;
 use tracing::Span;
@@ -16,12 +17,18 @@ pub async fn get_cat(State(state): State<Arc<AppState>>) -> Result<Html<String>,
     let client = &state.client;
 
     let link = get_link(client, API_URL).await.map_err(|e| {
-        tracing::error!(message = "Failed to get a link", error = ?e);
+        tracing::error!(
+            message = "Failed to get a link",
+            error = ErrorReport::from(&e).as_value()
+        );
         e
     })?;
 
     let raw_image = get_image(client, &link).await.map_err(|e| {
-        tracing::error!(message = "Failed to download image", error = ?e);
+        tracing::error!(
+            message = "Failed to download image",
+            error = ErrorReport::from(&e).as_value()
+        );
         e
     })?;
 
@@ -33,7 +40,10 @@ pub async fn get_cat(State(state): State<Arc<AppState>>) -> Result<Html<String>,
     .await
     .unwrap()
     .map_err(|e| {
-        tracing::error!(message = "Failed to process image", error = ?e);
+        tracing::error!(
+            message = "Failed to process image",
+            error = ErrorReport::from(&e).as_value()
+        );
         e
     })?;
 
@@ -8,6 +8,7 @@ mod tracing;
 async fn main() {
     let use_otel = std::env::var("USE_OTEL").is_ok();
     let show_spans = std::env::var("SHOW_SPANS").is_ok();
-    setup_tracing(use_otel, show_spans);
+    let pretty = std::env::var("PRETTY_LOGS").is_ok();
+    let _guard = setup_tracing(use_otel, show_spans, pretty);
     run::run().await.unwrap();
 }
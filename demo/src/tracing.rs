@@ -1,15 +1,28 @@
+use std::time::Duration;
+
 use layer::compat_layer::CompatLayer;
 use layer::fmt::json::JsonFormatter;
+use layer::fmt::pretty::PrettyFormatter;
+use layer::fmt::Format;
+use layer::non_blocking::{NonBlocking, WorkerGuard};
+use layer::slow_spans::SpanThresholds;
 use opentelemetry::global;
 use tracing::subscriber::set_global_default;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::{EnvFilter, Registry};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
 
-pub fn setup_tracing(use_otel: bool, show_spans: bool) {
+pub fn setup_tracing(use_otel: bool, show_spans: bool, pretty: bool) -> WorkerGuard {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("INFO"));
-    let subscriber = Registry::default()
-        .with(env_filter)
-        .with(CompatLayer::new(JsonFormatter::new(), std::io::stdout).with_spans(show_spans));
+    // Write to stdout from a background thread so a slow pipe doesn't hold up request handlers.
+    let (stdout, guard) = NonBlocking::new(std::io::stdout());
+    let compat: Box<dyn Layer<Layered<EnvFilter, Registry>> + Send + Sync> = if pretty {
+        Box::new(compat_layer(PrettyFormatter::new(), stdout, show_spans))
+    } else {
+        Box::new(compat_layer(JsonFormatter::new(), stdout, show_spans))
+    };
+    let subscriber = Registry::default().with(env_filter).with(compat);
 
     if use_otel {
         global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
@@ -27,4 +40,26 @@ pub fn setup_tracing(use_otel: bool, show_spans: bool) {
     } else {
         set_global_default(subscriber).expect("Failed to set subscriber");
     }
+
+    guard
+}
+
+fn compat_layer<S, F>(
+    formatter: F,
+    stdout: NonBlocking,
+    show_spans: bool,
+) -> CompatLayer<S, F, NonBlocking>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    F: Format<S>,
+{
+    // Fetching and converting a cat should take a couple of seconds at most, if it takes far longer
+    // then something is stuck and we want to know before the request eventually times out.
+    let slow_spans = SpanThresholds::new().name("get_cat", Duration::from_secs(3));
+    let hung_spans = SpanThresholds::new().name("get_cat", Duration::from_secs(30));
+
+    CompatLayer::new(formatter, stdout)
+        .with_spans(show_spans)
+        .with_slow_spans(slow_spans)
+        .with_hung_spans(hung_spans)
 }
@@ -0,0 +1,148 @@
+//! Counts the heap allocations made for each event, run with `cargo bench --bench allocations`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use layer::compat_layer::{CompatLayer, Visitor};
+use layer::fmt::json::JsonFormatter;
+use serde_json::Value;
+use tracing::field::{Field, Visit};
+use tracing::{info, info_span, Dispatch, Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const EVENTS: usize = 1000;
+
+// The average number of allocations for each of `EVENTS` calls of `event`, after a first call to
+// warm up thread locals and caches.
+fn allocations_per_event(dispatch: &Dispatch, event: impl Fn()) -> f64 {
+    tracing::dispatcher::with_default(dispatch, || {
+        event();
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..EVENTS {
+            event();
+        }
+        (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / EVENTS as f64
+    })
+}
+
+// Records the fields of events the way `Visitor` did before it had a field store of its own, as
+// JSON values in a map.
+#[derive(Default)]
+struct BTreeMapVisitor(BTreeMap<&'static str, Value>);
+
+impl Visit for BTreeMapVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name(), Value::from(format!("{:?}", value)));
+    }
+}
+
+// Only records the fields of events with a `V`, to compare visitors without formatting.
+struct RecordLayer<V>(PhantomData<fn() -> V>);
+
+impl<S: Subscriber, V: Visit + Default + 'static> Layer<S> for RecordLayer<V> {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = V::default();
+        event.record(&mut visitor);
+    }
+}
+
+fn record_dispatch<V: Visit + Default + 'static>() -> Dispatch {
+    Dispatch::new(tracing_subscriber::registry().with(RecordLayer::<V>(PhantomData)))
+}
+
+fn main() {
+    let dispatch = Dispatch::new(
+        tracing_subscriber::registry().with(CompatLayer::new(JsonFormatter::new(), io::sink)),
+    );
+
+    let scenarios: [(&str, &dyn Fn()); 4] = [
+        ("message only", &|| info!("Fetching link!")),
+        ("numbers and bools", &|| {
+            info!(
+                status = 200,
+                attempt = 1,
+                ratio = 0.5,
+                cached = true,
+                "Fetched"
+            )
+        }),
+        ("short strings", &|| {
+            info!(url = "https://cats", method = "GET", "Fetching link!")
+        }),
+        (
+            "debug values",
+            &|| info!(path = ?["cats", "dogs"], kind = ?Some(3), "Fetching link!"),
+        ),
+    ];
+
+    let span = tracing::dispatcher::with_default(&dispatch, || {
+        info_span!("get_cat", correlation_id = "abc", attempt = 1)
+    });
+    println!("{:<24}{:>16}{:>16}", "event", "no span", "in a span");
+    for (name, event) in scenarios {
+        let outside = allocations_per_event(&dispatch, event);
+        let inside = allocations_per_event(&dispatch, || span.in_scope(event));
+        println!("{:<24}{:>16.1}{:>16.1}", name, outside, inside);
+    }
+
+    let btree_map = record_dispatch::<BTreeMapVisitor>();
+    let visitor = record_dispatch::<Visitor<'static>>();
+    println!();
+    println!(
+        "{:<24}{:>16}{:>16}",
+        "recording only", "BTreeMap", "Visitor"
+    );
+    for (name, event) in scenarios {
+        let baseline = allocations_per_event(&btree_map, event);
+        let fields = allocations_per_event(&visitor, event);
+        println!("{:<24}{:>16.1}{:>16.1}", name, baseline, fields);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+use std::io;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use layer::compat_layer::CompatLayer;
+use layer::compat_span_ext::CompatSpanExt;
+use layer::fmt::bunyan::BunyanFormatter;
+use layer::fmt::json::JsonFormatter;
+use layer::fmt::{Format, FormatContext};
+use tracing::{info, info_span, Dispatch};
+use tracing_core::Event;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+// Formats events through the default `format_event_bytes`, the way `CompatLayer` did before the
+// formatters wrote bytes themselves and the way `AsFormatEvent` still does. Both formatters write
+// through `fmt::Write` there, chunk by chunk, and every chunk is checked to be UTF-8 again.
+struct ViaFmtWrite<F>(F);
+
+impl<F: Format<Registry>> Format<Registry> for ViaFmtWrite<F> {
+    fn format_event<W: fmt::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<Registry>,
+        writer: W,
+    ) -> fmt::Result {
+        self.0.format_event(event, ctx, writer)
+    }
+}
+
+fn dispatch<F: Format<Registry> + Send + Sync + 'static>(formatter: F) -> Dispatch {
+    Dispatch::new(tracing_subscriber::registry().with(CompatLayer::new(formatter, io::sink)))
+}
+
+fn log_event(dispatch: &Dispatch) {
+    tracing::dispatcher::with_default(dispatch, || {
+        let _cat = info_span!("get_cat", correlation_id = "abc").entered();
+        let _link = info_span!("get_cat_link", attempt = 1).entered();
+        for _ in 0..100 {
+            info!(
+                url = "https://cats",
+                status = 200,
+                "Fetching link with \"quotes\" and\nnewlines!"
+            );
+        }
+    });
+}
+
+fn bench_paths(c: &mut Criterion) {
+    let mut group = c.benchmark_group("json_100_events");
+    let fmt_write = dispatch(ViaFmtWrite(JsonFormatter::new()));
+    let bytes = dispatch(JsonFormatter::new());
+    group.bench_function("fmt_write", |b| b.iter(|| log_event(&fmt_write)));
+    group.bench_function("bytes", |b| b.iter(|| log_event(&bytes)));
+    group.finish();
+
+    let mut group = c.benchmark_group("bunyan_100_events");
+    let fmt_write = dispatch(ViaFmtWrite(BunyanFormatter::new("cats")));
+    let bytes = dispatch(BunyanFormatter::new("cats"));
+    group.bench_function("fmt_write", |b| b.iter(|| log_event(&fmt_write)));
+    group.bench_function("bytes", |b| b.iter(|| log_event(&bytes)));
+    group.finish();
+}
+
+// Logs from the innermost of `depth` nested spans. Every span has a `depth` field, which collides
+// with the other spans', and fields with keys of its own. Unless `cached`, a field is recorded on
+// the outermost span before each event, so that the span fields are rendered again every time.
+fn log_nested_event(dispatch: &Dispatch, depth: usize, cached: bool) {
+    tracing::dispatcher::with_default(dispatch, || {
+        let spans: Vec<_> = (0..depth)
+            .map(|i| {
+                let span = info_span!("nested", depth = i, attempt = tracing::field::Empty);
+                span.set_stored(format!("name_{}", i), "cats").unwrap();
+                span.set_stored(format!("cached_{}", i), true).unwrap();
+                span.entered()
+            })
+            .collect();
+        for attempt in 0..100 {
+            if !cached {
+                spans[0].record("attempt", attempt);
+            }
+            info!(url = "https://cats", status = 200, "Fetching link!");
+        }
+        spans.into_iter().rev().for_each(drop);
+    });
+}
+
+fn bench_nested_spans(c: &mut Criterion) {
+    let mut group = c.benchmark_group("json_100_events_in_nested_spans");
+    let json = dispatch(JsonFormatter::new());
+    for depth in [1, 5, 20] {
+        for (name, cached) in [("cached", true), ("uncached", false)] {
+            group.bench_with_input(BenchmarkId::new(name, depth), &depth, |b, &depth| {
+                b.iter(|| log_nested_event(&json, depth, cached))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_paths, bench_nested_spans);
+criterion_main!(benches);
@@ -0,0 +1,5 @@
+fn main() {
+    // `tracing_unstable` is set in RUSTFLAGS to record `valuable` fields, see the `valuable`
+    // feature. Declared here rather than under `[lints]`, which needs cargo 1.74.
+    println!("cargo:rustc-check-cfg=cfg(tracing_unstable)");
+}
@@ -0,0 +1,35 @@
+//! Turns the records written by `BinaryFormatter` back into JSON lines, reading from stdin and
+//! writing to stdout, e.g. `decode-logs --cbor < service.log`. MessagePack is the default.
+
+use std::io;
+use std::process::ExitCode;
+
+use layer::fmt::binary::{Decoder, Encoding};
+
+fn main() -> ExitCode {
+    let encoding = match std::env::args().nth(1).as_deref() {
+        None | Some("--msgpack") => Encoding::MessagePack,
+        Some("--cbor") => Encoding::Cbor,
+        Some(_) => {
+            eprintln!("usage: decode-logs [--msgpack | --cbor] < records");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let decoder = Decoder::new(io::stdin().lock(), encoding);
+    match decoder.write_json_lines(io::BufWriter::new(io::stdout().lock())) {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(skipped) => {
+            eprintln!(
+                "decode-logs: skipped {} records that couldn't be decoded",
+                skipped
+            );
+            ExitCode::FAILURE
+        }
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("decode-logs: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,26 +1,49 @@
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
 use std::collections::BTreeMap;
-use std::fmt;
-use std::io::Write;
+use std::error::Error;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
 use std::marker;
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
+use tracing_core::dispatcher::WeakDispatch;
 use tracing_core::field::{Field, Visit};
 use tracing_core::span::{Attributes, Id, Record};
-use tracing_core::{Dispatch, Event, Subscriber};
+use tracing_core::{Dispatch, Event, Level, Metadata, Subscriber};
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::Context;
-use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
 use tracing_subscriber::Layer;
 
-use crate::fmt::Format;
+use crate::error::error_value;
+use crate::fields::{FieldMap, FieldOrder, FieldStr, FieldValue, Numbers};
+use crate::fmt::{ansi_for, format_duration, Format, FormatContext};
+use crate::non_blocking::NonBlocking;
+use crate::redaction::Redaction;
+use crate::slow_spans::{SpanThresholds, Watchdog};
+use crate::span_events::{
+    event_metadata, span_event_metadata, DurationFields, SpanEvents, SpanMessages, Timings,
+};
 
 pub struct CompatLayer<S, F, W> {
     formatter: F,
     get_context: WithContext,
     make_writer: W,
-    with_spans: bool,
+    span_events: SpanEvents,
+    span_event_level: Option<Level>,
+    span_messages: SpanMessages,
+    duration_fields: DurationFields,
+    field_options: FieldOptions,
+    error_backtraces: bool,
+    // Whether formatters may color the output, see `with_ansi`.
+    ansi: bool,
+    slow_spans: Option<SpanThresholds>,
+    hung_spans: Option<(SpanThresholds, Arc<Watchdog>)>,
+    // The subscriber this layer is part of, which slow and hung span warnings are dispatched to.
+    dispatch: OnceLock<WeakDispatch>,
     _registry: marker::PhantomData<S>,
 }
 
@@ -30,20 +53,306 @@ pub struct CompatLayer<S, F, W> {
 //
 // See https://github.com/tokio-rs/tracing/blob/4dad420ee1d4607bad79270c1520673fa6266a3d/tracing-error/src/layer.rs
 pub(crate) struct WithContext(
-    #[allow(clippy::type_complexity)] fn(&Dispatch, &Id, f: &mut dyn FnMut(&Visitor) -> bool),
+    #[allow(clippy::type_complexity)]
+    fn(&Dispatch, &Id, Walk, f: &mut dyn FnMut(&mut Visitor<'static>) -> bool),
 );
 
+/// The spans visited by `WithContext::with_context`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Walk {
+    /// Only the span itself.
+    Current,
+    /// The span and its ancestors, starting with the root.
+    FromRoot,
+    /// The span and its ancestors, starting with the span.
+    FromLeaf,
+}
+
 impl WithContext {
+    pub(crate) fn new<S>() -> Self
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    {
+        WithContext(get_context::<S>)
+    }
+
     // This function allows a function to be called in the context of the
-    // "remembered" subscriber.
+    // "remembered" subscriber, it stops visiting spans once `f` returns true.
     pub(crate) fn with_context(
         &self,
         dispatch: &Dispatch,
         id: &Id,
-        mut f: impl FnMut(&Visitor) -> bool,
+        walk: Walk,
+        mut f: impl FnMut(&mut Visitor<'static>) -> bool,
     ) {
-        (self.0)(dispatch, id, &mut f)
+        (self.0)(dispatch, id, walk, &mut f)
+    }
+}
+
+fn get_context<S>(
+    dispatch: &Dispatch,
+    id: &Id,
+    walk: Walk,
+    f: &mut dyn FnMut(&mut Visitor<'static>) -> bool,
+) where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    let subscriber = dispatch
+        .downcast_ref::<S>()
+        .expect("subscriber should downcast to expected type; this is a bug!");
+    let span = subscriber
+        .span(id)
+        .expect("registry should have a span for the current ID");
+
+    let mut visit = |span: SpanRef<'_, S>| {
+        let mut extensions = span.extensions_mut();
+        extensions.get_mut::<Visitor>().is_some_and(&mut *f)
+    };
+
+    match walk {
+        Walk::Current => {
+            visit(span);
+        }
+        Walk::FromRoot => {
+            for span in span.scope().from_root() {
+                if visit(span) {
+                    return;
+                }
+            }
+        }
+        Walk::FromLeaf => {
+            for span in span.scope() {
+                if visit(span) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// What is done to the fields of spans and events once they're recorded, before they're stored or
+/// formatted.
+#[derive(Clone, Debug)]
+pub(crate) struct FieldOptions {
+    // Tells the options of one layer from another's, see `record_values`.
+    layer: u64,
+    pub(crate) redaction: Option<Redaction>,
+    pub(crate) order: FieldOrder,
+    pub(crate) numbers: Numbers,
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    pub(crate) value_limits: crate::fields::ValueLimits,
+}
+
+impl Default for FieldOptions {
+    fn default() -> Self {
+        static NEXT_LAYER: AtomicU64 = AtomicU64::new(1);
+
+        Self {
+            layer: NEXT_LAYER.fetch_add(1, Ordering::Relaxed),
+            redaction: None,
+            order: FieldOrder::default(),
+            numbers: Numbers::default(),
+            #[cfg(all(tracing_unstable, feature = "valuable"))]
+            value_limits: crate::fields::ValueLimits::default(),
+        }
+    }
+}
+
+impl FieldOptions {
+    // A visitor that records fields the way these options say to.
+    pub(crate) fn visitor(&self) -> Visitor<'static> {
+        Visitor {
+            recorded_by: self.layer,
+            #[cfg(all(tracing_unstable, feature = "valuable"))]
+            value_limits: self.value_limits,
+            ..Visitor::default()
+        }
+    }
+
+    pub(crate) fn apply(
+        &self,
+        declared: impl IntoIterator<Item = Field>,
+        fields: &mut FieldMap<'_>,
+    ) {
+        self.process(fields);
+        self.order.sort(declared, fields);
+    }
+
+    // Everything but sorting, which needs all of a span's fields.
+    pub(crate) fn process(&self, fields: &mut FieldMap<'_>) {
+        if let Some(redaction) = &self.redaction {
+            redaction.redact(fields);
+        }
+        self.numbers.apply(fields);
+    }
+}
+
+// Records the span's attributes for later use as we won't get another chance to access them.
+// Several layers may want the fields recorded so only the first one to get here does it, with its
+// own options.
+pub(crate) fn record_new_span<S>(
+    attrs: &Attributes<'_>,
+    id: &Id,
+    ctx: &Context<'_, S>,
+    options: &FieldOptions,
+) where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    let span = ctx.span(id).expect("Span not found, this is a bug");
+    let mut extensions = span.extensions_mut();
+    if extensions.get_mut::<Visitor>().is_none() {
+        let mut visitor = options.visitor();
+        attrs.record(&mut visitor);
+        options.apply(attrs.metadata().fields(), visitor.fields_mut());
+        extensions.insert(visitor);
+    }
+}
+
+pub(crate) fn record_values<S>(
+    id: &Id,
+    values: &Record<'_>,
+    ctx: &Context<'_, S>,
+    options: &FieldOptions,
+) where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    let span = ctx.span(id).expect("Span not found, this is a bug");
+    let mut extensions = span.extensions_mut();
+
+    // We stored the visitor when we created the span, we *should* be able to access it now.
+    let visitor = extensions
+        .get_mut::<Visitor>()
+        .expect("Visitor not found on 'record', this is a bug");
+    // Values are recorded by the layer that recorded the span's attributes, with the same options,
+    // another layer would overwrite them with its own take on them.
+    if visitor.recorded_by != options.layer {
+        return;
+    }
+
+    // Process the new values on their own so that nothing unredacted is ever stored.
+    let mut recorded = options.visitor();
+    values.record(&mut recorded);
+    options.process(recorded.fields_mut());
+    visitor.fields_mut().append(recorded.fields_mut());
+    options
+        .order
+        .sort(span.metadata().fields(), visitor.fields_mut());
+}
+
+// The context given to formatters by `CompatLayer`, it applies the layer's field options to the
+// fields of events.
+struct CompatContext<'a, S> {
+    ctx: Context<'a, S>,
+    fields: &'a FieldOptions,
+    error_backtraces: bool,
+    ansi: bool,
+    span_event: Option<SpanEvents>,
+}
+
+impl<S> FormatContext<S> for CompatContext<'_, S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn span(&self, id: &Id) -> Option<SpanRef<'_, S>> {
+        self.ctx.span(id)
+    }
+
+    fn lookup_current(&self) -> Option<SpanRef<'_, S>> {
+        self.ctx.lookup_current()
+    }
+
+    fn layer_context(&self) -> Option<Context<'_, S>> {
+        Some(self.ctx.clone())
+    }
+
+    fn record_event(&self, event: &Event<'_>) -> Visitor<'static> {
+        let mut visitor = Visitor {
+            backtraces: self.error_backtraces && *event.metadata().level() == Level::ERROR,
+            ..self.fields.visitor()
+        };
+        event.record(&mut visitor);
+        self.fields.apply(event.fields(), visitor.fields_mut());
+        visitor
     }
+
+    fn span_event(&self) -> Option<SpanEvents> {
+        self.span_event
+    }
+
+    fn ansi(&self) -> Option<bool> {
+        Some(self.ansi)
+    }
+}
+
+macro_rules! with_event_from_span {
+    ($id:expr, $meta:expr, $($field:literal = $value:expr),*, |$event:ident| $code:block) => {
+        let meta: &'static tracing_core::Metadata<'static> = $meta;
+        let cs = meta.callsite();
+        let fs = tracing_core::field::FieldSet::new(&[$($field),*], cs);
+        #[allow(unused)]
+        let mut iter = fs.iter();
+        let v = [$(
+            (&iter.next().unwrap(), Some(&$value as &dyn tracing_core::field::Value)),
+        )*];
+        let vs = fs.value_set(&v);
+        let $event = tracing_core::Event::new_child_of($id, meta, &vs);
+        $code
+    };
+}
+
+// The fields of span events, which need to match the fields given to `with_event_from_span!`
+// when the level is overridden.
+const MESSAGE_FIELDS: &[&str] = &["message"];
+const CLOSE_FIELDS: &[&str] = &[
+    "message",
+    "elapsed",
+    "busy",
+    "idle",
+    "elapsed_ns",
+    "busy_ns",
+    "idle_ns",
+    "elapsed_ms",
+    "busy_ms",
+    "idle_ms",
+];
+const THRESHOLD_FIELDS: &[&str] = &["message", "elapsed_ms", "threshold_ms"];
+
+// Warns about a span that is past its threshold or deadline. The warning is dispatched to the whole
+// subscriber, so that filters and other layers see it like any other event.
+fn warn_span(
+    dispatch: &Dispatch,
+    id: &Id,
+    span: &'static Metadata<'static>,
+    message: &str,
+    age: Duration,
+    threshold: Duration,
+) {
+    // Event metadata of its own even for WARN spans, as layers tell events from spans by it.
+    let meta = event_metadata(span, Level::WARN, THRESHOLD_FIELDS);
+    if !dispatch.enabled(meta) {
+        return;
+    }
+
+    let elapsed = as_millis(age);
+    let threshold = as_millis(threshold);
+    with_event_from_span!(
+        id.clone(),
+        meta,
+        "message" = message,
+        "elapsed_ms" = elapsed,
+        "threshold_ms" = threshold,
+        |event| {
+            dispatch.event(&event);
+        }
+    );
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn as_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
 }
 
 impl<S, F, W> CompatLayer<S, F, W>
@@ -55,112 +364,323 @@ where
     pub fn new(formatter: F, make_writer: W) -> Self {
         Self {
             formatter,
-            get_context: WithContext(Self::get_context),
+            get_context: WithContext::new::<S>(),
+            ansi: ansi_for(&io::stdout()),
             make_writer,
-            with_spans: false,
+            span_events: SpanEvents::NONE,
+            span_event_level: None,
+            span_messages: SpanMessages::default(),
+            duration_fields: DurationFields::default(),
+            field_options: FieldOptions::default(),
+            error_backtraces: false,
+            slow_spans: None,
+            hung_spans: None,
+            dispatch: OnceLock::new(),
             _registry: marker::PhantomData,
         }
     }
 
+    /// Logs a "start" event the first time each span is entered and an "end" event, with the time
+    /// spent in the span, when it closes. Shorthand for `SpanEvents::FIRST_ENTER | SpanEvents::CLOSE`.
     pub fn with_spans(mut self, with_spans: bool) -> Self {
-        self.with_spans = with_spans;
+        self.span_events = match with_spans {
+            true => SpanEvents::FIRST_ENTER | SpanEvents::CLOSE,
+            false => SpanEvents::NONE,
+        };
         self
     }
 
-    fn get_context(dispatch: &Dispatch, id: &Id, f: &mut dyn FnMut(&Visitor) -> bool) {
-        let subscriber = dispatch
-            .downcast_ref::<S>()
-            .expect("subscriber should downcast to expected type; this is a bug!");
-        let span = subscriber
-            .span(id)
-            .expect("registry should have a span for the current ID");
+    /// Sets which points in the lifecycle of spans are logged as events.
+    pub fn with_span_events(mut self, span_events: SpanEvents) -> Self {
+        self.span_events = span_events;
+        self
+    }
 
-        for span in span.scope().from_root() {
-            let mut extensions = span.extensions_mut();
-            if let Some(visitor) = extensions.get_mut::<Visitor>() {
-                if f(visitor) {
-                    return;
-                }
-            }
-        }
+    /// Logs span events at this level instead of the level of their span.
+    pub fn with_span_event_level(mut self, level: Level) -> Self {
+        self.span_event_level = Some(level);
+        self
+    }
+
+    /// Sets the `message` of each kind of span event.
+    pub fn with_span_messages(mut self, messages: SpanMessages) -> Self {
+        self.span_messages = messages;
+        self
+    }
+
+    /// Sets which fields the durations on the close event are written to, the default is only the
+    /// text of `format_duration`.
+    pub fn with_duration_fields(mut self, fields: DurationFields) -> Self {
+        self.duration_fields = fields;
+        self
+    }
+
+    /// Scrubs sensitive fields of spans and events, spans are redacted before their fields are
+    /// stored in the span's extensions so unredacted values never reach a formatter.
+    ///
+    /// The fields of a span are stored once for every layer, by the first `CompatLayer` or
+    /// `FieldRecorder` to see the span, with its redaction, field order, numbers and value limits.
+    /// With several of them on one registry, give them all the same ones, the others only apply
+    /// to the fields of events.
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.field_options.redaction = Some(redaction).filter(|r| !r.is_empty());
+        self
+    }
+
+    /// Sets the order the fields of each span and event are written in, alphabetical by default.
+    pub fn with_field_order(mut self, order: FieldOrder) -> Self {
+        self.field_options.order = order;
+        self
+    }
+
+    /// Sets how NaN and infinite floats, and integers too large for JavaScript, are written. By
+    /// default non-finite floats are `null` and integers are written as they are.
+    pub fn with_numbers(mut self, numbers: Numbers) -> Self {
+        self.field_options.numbers = numbers;
+        self
+    }
+
+    /// Sets how large the JSON that `valuable` fields are recorded as can get.
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    pub fn with_value_limits(mut self, limits: crate::fields::ValueLimits) -> Self {
+        self.field_options.value_limits = limits;
+        self
+    }
+
+    /// Lets formatters that support it, like `PrettyFormatter`, color the output with ANSI escape
+    /// codes. `MakeWriter` doesn't say where it writes to, so by default colors are on if stdout is
+    /// a terminal and the `NO_COLOR` environment variable isn't set.
+    pub fn with_ansi(mut self, ansi: bool) -> Self {
+        self.ansi = ansi;
+        self
+    }
+
+    /// Captures a backtrace where errors are recorded as fields of ERROR events, unless the error
+    /// brought one of its own, e.g. an `anyhow::Error` recorded through `ErrorReport`. Capturing
+    /// is slow, so this is best left for services that rarely log errors.
+    pub fn with_error_backtraces(mut self, error_backtraces: bool) -> Self {
+        self.error_backtraces = error_backtraces;
+        self
+    }
+
+    /// Logs a WARN event when a span closes more than its threshold after it was created, whether
+    /// or not span events are on. The event has the span's fields, and those of its parents, along
+    /// with `elapsed_ms` and `threshold_ms`.
+    ///
+    /// The event is dispatched to the whole subscriber rather than just this layer, so it goes
+    /// through the same filters and layers as any other event.
+    pub fn with_slow_spans(mut self, thresholds: SpanThresholds) -> Self {
+        self.slow_spans = Some(thresholds).filter(|t| !t.is_empty());
+        self
+    }
+
+    /// Logs a WARN event, once, for each span that is still open past its deadline, e.g. a
+    /// request that is stuck, with the same fields as `with_slow_spans`.
+    ///
+    /// Deadlines are checked from a thread of its own that is started when the subscriber is
+    /// registered, so spans are reported even if nothing else is being logged. Like slow spans, the
+    /// event is dispatched to the whole subscriber.
+    pub fn with_hung_spans(mut self, deadlines: SpanThresholds) -> Self {
+        self.hung_spans = Some(deadlines)
+            .filter(|d| !d.is_empty())
+            .map(|deadlines| (deadlines, Arc::new(Watchdog::new())));
+        self
     }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Visitor<'a> {
-    fields: BTreeMap<&'a str, serde_json::Value>,
+    // Keys are usually the `'static` names of fields, but values stored through `CompatSpanExt`
+    // can have any name.
+    fields: FieldMap<'a>,
+    // Changes whenever the fields might have, so that anything derived from them can tell when
+    // it's stale.
+    version: u64,
+    // Whether to capture a backtrace for errors that don't have one.
+    backtraces: bool,
+    // The layer whose field options the fields were recorded with, see `FieldOptions`.
+    recorded_by: u64,
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    value_limits: crate::fields::ValueLimits,
 }
 
 impl<'a> Visitor<'a> {
-    pub fn fields(&self) -> &BTreeMap<&'a str, serde_json::Value> {
+    pub fn fields(&self) -> &FieldMap<'a> {
         &self.fields
     }
 
-    pub fn fields_mut(&mut self) -> &mut BTreeMap<&'a str, serde_json::Value> {
+    /// The fields as JSON values sorted by name, the way they were kept before `FieldMap`.
+    /// Every value is converted, so prefer `fields` where that's not needed.
+    pub fn fields_json(&self) -> BTreeMap<&str, serde_json::Value> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_ref(), value.to_json()))
+            .collect()
+    }
+
+    pub fn fields_mut(&mut self) -> &mut FieldMap<'a> {
+        self.version += 1;
         &mut self.fields
     }
+
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn insert(&mut self, name: &'a str, value: impl Into<FieldValue>) {
+        self.fields_mut().insert(name, value);
+    }
 }
 
 impl Visit for Visitor<'_> {
     fn record_i64(&mut self, field: &Field, value: i64) {
-        self.fields
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), value);
     }
 
     fn record_u64(&mut self, field: &Field, value: u64) {
-        self.fields
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), value);
     }
 
     fn record_f64(&mut self, field: &Field, value: f64) {
-        self.fields
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), value);
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        self.insert(field.name(), value);
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.insert(field.name(), value);
     }
 
     fn record_bool(&mut self, field: &Field, value: bool) {
-        self.fields
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), value);
     }
 
     fn record_str(&mut self, field: &Field, value: &str) {
-        self.fields
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), value);
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn Error + 'static)) {
+        self.insert(field.name(), error_value(value, self.backtraces));
+    }
+
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    fn record_value(&mut self, field: &Field, value: valuable::Value<'_>) {
+        self.insert(
+            field.name(),
+            crate::fields::valuable_to_json(value, self.value_limits),
+        );
     }
 
     fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
         match field.name() {
             // Skip fields that are actually log metadata that have already been handled
             name if name.starts_with("log.") => (),
-            name if name.starts_with("r#") => {
-                self.fields
-                    .insert(&name[2..], serde_json::Value::from(format!("{:?}", value)));
-            }
             name => {
-                self.fields
-                    .insert(name, serde_json::Value::from(format!("{:?}", value)));
+                let name = name.strip_prefix("r#").unwrap_or(name);
+                let mut text = FieldStr::new();
+                let _ = write!(text, "{:?}", value);
+                self.insert(name, FieldValue::Str(text));
             }
         };
     }
 }
 
-macro_rules! with_event_from_span {
-    ($id:ident, $span:ident, $($field:literal = $value:expr),*, |$event:ident| $code:block) => {
-        let meta = $span.metadata();
-        let cs = meta.callsite();
-        let fs = tracing_core::field::FieldSet::new(&[$($field),*], cs);
-        #[allow(unused)]
-        let mut iter = fs.iter();
-        let v = [$(
-            (&iter.next().unwrap(), Some(&$value as &dyn tracing_core::field::Value)),
-        )*];
-        let vs = fs.value_set(&v);
-        let $event = tracing_core::Event::new_child_of($id, meta, &vs);
-        $code
-    };
-}
+impl<S, F, W> CompatLayer<S, F, W>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    F: Format<S> + 'static,
+    W: for<'writer> MakeWriter<'writer> + 'static,
+{
+    fn write_event(&self, event: &Event<'_>, ctx: Context<'_, S>, span_event: Option<SpanEvents>) {
+        // We can avoid extra allocations by using a thread local here.
+        thread_local! {
+            static BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+        }
 
-/// New type around the instant to avoid interfering with other layers.
-pub(crate) struct InstantWrapper(Instant);
+        BUF.with(|buf| {
+            let borrow = buf.try_borrow_mut();
+            let mut a;
+            let mut b;
+            let buf = match borrow {
+                Ok(buf) => {
+                    a = buf;
+                    &mut *a
+                }
+                _ => {
+                    b = Vec::new();
+                    &mut b
+                }
+            };
+
+            let ctx = CompatContext {
+                ctx,
+                fields: &self.field_options,
+                error_backtraces: self.error_backtraces,
+                ansi: self.ansi,
+                span_event,
+            };
+            // Don't write what was formatted before an error, a partial frame would corrupt the
+            // rest of a binary stream.
+            if self.formatter.format_event_bytes(event, &ctx, buf).is_ok() {
+                let _ = self.make_writer.make_writer().write_all(buf);
+            }
+            buf.clear();
+        })
+    }
+
+    fn span_event(&self, id: &Id, ctx: Context<'_, S>, kind: SpanEvents, message: &str) {
+        if !self.span_events.contains(kind) {
+            return;
+        }
+
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        let meta = span_event_metadata(span.metadata(), self.span_event_level, MESSAGE_FIELDS);
+        // The span passed the filters at its own level, which the event may not be at.
+        if !ctx.enabled(meta) {
+            return;
+        }
+        with_event_from_span!(id, meta, "message" = message, |event| {
+            drop(span);
+            self.write_event(&event, ctx, Some(kind));
+        });
+    }
+
+    fn slow_span(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(thresholds) = &self.slow_spans else {
+            return;
+        };
+        let Some(dispatch) = self.dispatch.get().and_then(WeakDispatch::upgrade) else {
+            return;
+        };
+
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        let Some(threshold) = thresholds.threshold(span.metadata()) else {
+            return;
+        };
+
+        let age = span
+            .extensions()
+            .get::<Timings>()
+            .expect("Timings not found, this is a bug")
+            .age();
+        if age <= threshold {
+            return;
+        }
+
+        let meta = span.metadata();
+        drop(span);
+        warn_span(
+            &dispatch,
+            id,
+            meta,
+            &self.span_messages.slow,
+            age,
+            threshold,
+        );
+    }
+}
 
 impl<S, F, W> Layer<S> for CompatLayer<S, F, W>
 where
@@ -168,93 +688,133 @@ where
     F: Format<S> + 'static,
     W: for<'writer> MakeWriter<'writer> + 'static,
 {
-    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
-        // We record the span's attributes for later use as we won't get another chance to access
-        // them.
-        let span = ctx.span(id).expect("Span not found, this is a bug");
-        let mut visitor: Visitor<'_> = Visitor::default();
-        attrs.record(&mut visitor);
-        span.extensions_mut().insert(visitor);
-    }
+    fn on_register_dispatch(&self, dispatch: &Dispatch) {
+        let _ = self.dispatch.set(dispatch.downgrade());
+        // The lines a `NonBlocking` writer drops are reported to the subscriber writing to it.
+        if let Some(non_blocking) = (&self.make_writer as &dyn Any).downcast_ref::<NonBlocking>() {
+            non_blocking.report_to(dispatch);
+        }
+        let Some((_, watchdog)) = &self.hung_spans else {
+            return;
+        };
 
-    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
-        let span = ctx.span(span).expect("Span not found, this is a bug");
-        let mut extensions = span.extensions_mut();
+        let message = self.span_messages.hung.clone();
+        watchdog.start(
+            dispatch,
+            Box::new(move |dispatch, id, meta, age, deadline| {
+                warn_span(dispatch, id, meta, &message, age, deadline);
+            }),
+        );
+    }
 
-        // We stored the visitor when we created the span, we *should* be able to access it now.
-        let visitor = extensions
-            .get_mut::<Visitor>()
-            .expect("Visitor not found on 'record', this is a bug");
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        record_new_span(attrs, id, &ctx, &self.field_options);
+        if let Some((deadlines, watchdog)) = &self.hung_spans {
+            if let Some(deadline) = deadlines.threshold(attrs.metadata()) {
+                watchdog.watch(id, attrs.metadata(), deadline);
+            }
+        }
+        {
+            let span = ctx.span(id).expect("Span not found, this is a bug");
+            let mut extensions = span.extensions_mut();
+            if extensions.get_mut::<Timings>().is_none() {
+                extensions.insert(Timings::new());
+            }
+        }
+        self.formatter.on_new_span(attrs, id, ctx.clone());
+        self.span_event(id, ctx, SpanEvents::NEW, &self.span_messages.new);
+    }
 
-        values.record(visitor);
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        record_values(id, values, &ctx, &self.field_options);
+        self.formatter.on_record(id, values, ctx);
     }
 
     fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
-        let span = ctx.span(id).expect("Span not found, this is a bug");
-
-        let mut extensions = span.extensions_mut();
+        let first_entry = {
+            let span = ctx.span(id).expect("Span not found, this is a bug");
+            let mut extensions = span.extensions_mut();
+            extensions
+                .get_mut::<Timings>()
+                .map(Timings::enter)
+                .unwrap_or(false)
+        };
 
-        // A span can be entered multiple times in an async context, but we only worry about
-        // recording the total duration of the span, not the idle + active time, so we insert the
-        // instant once, on the first time the span is entered.
-        let first_entry = extensions.get_mut::<InstantWrapper>().is_none();
         if first_entry {
-            extensions.insert(InstantWrapper(Instant::now()));
+            let message = &self.span_messages.first_enter;
+            self.span_event(id, ctx.clone(), SpanEvents::FIRST_ENTER, message);
+        }
+        self.span_event(id, ctx, SpanEvents::ENTER, &self.span_messages.enter);
+    }
 
-            if self.with_spans {
-                // We also make use of the first span entry to "log" the start.
-                with_event_from_span!(id, span, "message" = "start", |event| {
-                    drop(extensions);
-                    drop(span);
-                    self.on_event(&event, ctx);
-                });
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        {
+            let span = ctx.span(id).expect("Span not found, this is a bug");
+            let mut extensions = span.extensions_mut();
+            if let Some(timings) = extensions.get_mut::<Timings>() {
+                timings.exit();
             }
         }
+
+        self.span_event(id, ctx, SpanEvents::EXIT, &self.span_messages.exit);
     }
 
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
-        if self.with_spans {
+        if let Some((_, watchdog)) = &self.hung_spans {
+            watchdog.unwatch(&id);
+        }
+        self.slow_span(&id, ctx.clone());
+
+        if self.span_events.contains(SpanEvents::CLOSE) {
             let span = ctx.span(&id).expect("Span not found, this is a bug");
-            let start = span
+            let meta = span_event_metadata(span.metadata(), self.span_event_level, CLOSE_FIELDS);
+            if !ctx.enabled(meta) {
+                return;
+            }
+            let (elapsed, busy, idle) = span
                 .extensions()
-                .get::<InstantWrapper>()
-                .map(|i| i.0)
-                .expect("Start not found, this is a bug");
-
-            let elapsed = crate::fmt::format_duration(start.elapsed());
-
-            with_event_from_span!(id, span, "message" = "end", "elapsed" = elapsed, |event| {
-                drop(span);
-                self.on_event(&event, ctx);
-            });
+                .get::<Timings>()
+                .expect("Timings not found, this is a bug")
+                .durations();
+
+            // Fields that are `None` aren't recorded.
+            let fields = self.duration_fields;
+            let text = |d| {
+                fields
+                    .contains(DurationFields::TEXT)
+                    .then(|| format_duration(d))
+            };
+            let nanos = |d| fields.contains(DurationFields::NANOS).then(|| as_nanos(d));
+            let millis = |d| {
+                fields
+                    .contains(DurationFields::MILLIS)
+                    .then(|| as_millis(d))
+            };
+            let message = self.span_messages.close.as_ref();
+
+            with_event_from_span!(
+                id,
+                meta,
+                "message" = message,
+                "elapsed" = text(elapsed),
+                "busy" = text(busy),
+                "idle" = text(idle),
+                "elapsed_ns" = nanos(elapsed),
+                "busy_ns" = nanos(busy),
+                "idle_ns" = nanos(idle),
+                "elapsed_ms" = millis(elapsed),
+                "busy_ms" = millis(busy),
+                "idle_ms" = millis(idle),
+                |event| {
+                    drop(span);
+                    self.write_event(&event, ctx, Some(SpanEvents::CLOSE));
+                }
+            );
         }
     }
 
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
-        // We can avoid extra allocations by using a thread local here.
-        thread_local! {
-            static BUF: RefCell<String> = RefCell::new(String::new());
-        }
-
-        BUF.with(|buf| {
-            let borrow = buf.try_borrow_mut();
-            let mut a;
-            let mut b;
-            let buf = match borrow {
-                Ok(buf) => {
-                    a = buf;
-                    &mut *a
-                }
-                _ => {
-                    b = String::new();
-                    &mut b
-                }
-            };
-
-            let _ = self.formatter.format_event(event, ctx, &mut *buf);
-            let _ = self.make_writer.make_writer().write_all(buf.as_bytes());
-            buf.clear();
-        })
+        self.write_event(event, ctx, None);
     }
 
     // SAFETY: The pointer returned by downcast_ref is non-null and points to a valid instance of
@@ -266,6 +826,9 @@ where
             id if id == TypeId::of::<WithContext>() => {
                 Some(&self.get_context as *const _ as *const ())
             }
+            id if id == TypeId::of::<FieldOptions>() => {
+                Some(&self.field_options as *const _ as *const ())
+            }
             _ => None,
         }
     }
@@ -1,25 +1,149 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use tracing::Span;
 
-use crate::compat_layer::WithContext;
+use crate::compat_layer::{FieldOptions, Visitor, Walk, WithContext};
+use crate::fields::{FieldMap, FieldValue};
+
+/// Which value wins when a key is stored on more than one span in a span's scope.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Precedence {
+    /// The value stored on the span closest to the current one, i.e. the same value that ends up
+    /// in the current span's log lines.
+    #[default]
+    Nearest,
+    /// The value stored on the span closest to the root.
+    RootMost,
+}
 
+impl Precedence {
+    fn walk(self) -> Walk {
+        match self {
+            Precedence::Nearest => Walk::FromLeaf,
+            Precedence::RootMost => Walk::FromRoot,
+        }
+    }
+}
+
+/// Access to the fields stored by `CompatLayer` (or `FieldRecorder`) for a span and its
+/// ancestors.
+///
+/// All methods do nothing, or return nothing, if neither layer is installed or the span is
+/// disabled.
 pub trait CompatSpanExt {
     /// Get the correlation_id from the current span, if it exists.
+    ///
+    /// The value stored closest to the root wins.
     fn get_stored<T: From<serde_json::Value>>(&self, key: &str) -> Option<T>;
+
+    /// Get a stored value and deserialize it, returns `None` if it isn't stored or doesn't
+    /// deserialize to `T`.
+    fn get_stored_as<T: DeserializeOwned>(&self, key: &str, precedence: Precedence) -> Option<T>;
+
+    /// Store a value on this span, it will show up in the log lines of this span and its
+    /// descendants like any other field.
+    fn set_stored(
+        &self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Serialize,
+    ) -> Result<(), serde_json::Error>;
+
+    /// Remove a value stored on this span, values stored on ancestors are left alone.
+    fn remove_stored(&self, key: &str) -> Option<serde_json::Value>;
+
+    /// All the values stored on this span and its ancestors.
+    fn get_all_stored(&self, precedence: Precedence) -> BTreeMap<String, serde_json::Value>;
 }
 
 impl CompatSpanExt for Span {
     fn get_stored<T: From<serde_json::Value>>(&self, key: &str) -> Option<T> {
-        let mut val = None;
+        find(self, key, Walk::FromRoot).map(T::from)
+    }
+
+    fn get_stored_as<T: DeserializeOwned>(&self, key: &str, precedence: Precedence) -> Option<T> {
+        find(self, key, precedence.walk()).and_then(|v| serde_json::from_value(v).ok())
+    }
+
+    fn set_stored(
+        &self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Serialize,
+    ) -> Result<(), serde_json::Error> {
+        let mut entry = FieldMap::new();
+        entry.insert(key.into(), FieldValue::from(serde_json::to_value(value)?));
 
         self.with_subscriber(|(id, dispatch)| {
-            if let Some(get_context) = dispatch.downcast_ref::<WithContext>() {
-                get_context.with_context(dispatch, id, |storage| {
-                    val = storage.fields().get(key).map(|v| T::from(v.clone()));
-                    val.is_some()
-                })
-            }
+            let (Some(get_context), Some(options), Some(metadata)) = (
+                dispatch.downcast_ref::<WithContext>(),
+                dispatch.downcast_ref::<FieldOptions>(),
+                self.metadata(),
+            ) else {
+                return;
+            };
+
+            // Stored values are redacted and sorted like the fields they're stored alongside.
+            options.process(&mut entry);
+            get_context.with_context(dispatch, id, Walk::Current, |storage| {
+                storage.fields_mut().append(&mut entry);
+                options.order.sort(metadata.fields(), storage.fields_mut());
+                true
+            });
+        });
+
+        Ok(())
+    }
+
+    fn remove_stored(&self, key: &str) -> Option<serde_json::Value> {
+        let mut val = None;
+
+        with_context(self, Walk::Current, |storage| {
+            val = storage
+                .fields_mut()
+                .remove(key)
+                .map(serde_json::Value::from);
+            true
         });
 
         val
     }
+
+    fn get_all_stored(&self, precedence: Precedence) -> BTreeMap<String, serde_json::Value> {
+        let mut all = BTreeMap::new();
+
+        // Visit the spans with the lowest precedence first so that later ones overwrite them.
+        let walk = match precedence {
+            Precedence::Nearest => Walk::FromRoot,
+            Precedence::RootMost => Walk::FromLeaf,
+        };
+        with_context(self, walk, |storage| {
+            for (key, value) in storage.fields() {
+                all.insert(key.to_string(), value.to_json());
+            }
+            false
+        });
+
+        all
+    }
+}
+
+fn with_context(span: &Span, walk: Walk, f: impl FnMut(&mut Visitor<'static>) -> bool) {
+    span.with_subscriber(|(id, dispatch)| {
+        if let Some(get_context) = dispatch.downcast_ref::<WithContext>() {
+            get_context.with_context(dispatch, id, walk, f)
+        }
+    });
+}
+
+fn find(span: &Span, key: &str, walk: Walk) -> Option<serde_json::Value> {
+    let mut val = None;
+
+    with_context(span, walk, |storage| {
+        val = storage.fields().get(key).map(FieldValue::to_json);
+        val.is_some()
+    });
+
+    val
 }
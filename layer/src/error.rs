@@ -0,0 +1,101 @@
+use std::backtrace::Backtrace;
+use std::error::Error;
+use std::fmt;
+use std::iter;
+
+use serde_json::{Map, Value};
+
+/// An error with what can't be found out from a `&dyn Error` alone, its type name and, for an
+/// `anyhow::Error`, the backtrace it captured.
+///
+/// Errors recorded as `&dyn Error` fields are written as an object with their `message`, the
+/// messages of their `sources`, and for an `ErrorReport` the error's `type` and `backtrace`.
+///
+/// ```
+/// use layer::error::ErrorReport;
+///
+/// let error = "cat".parse::<u32>().unwrap_err();
+/// tracing::error!(error = ErrorReport::new(&error).as_value(), "Failed to parse");
+/// ```
+#[derive(Debug)]
+pub struct ErrorReport {
+    message: String,
+    sources: Vec<String>,
+    type_name: Option<&'static str>,
+    backtrace: Option<String>,
+}
+
+impl ErrorReport {
+    pub fn new<E: Error + 'static>(error: &E) -> Self {
+        Self {
+            message: error.to_string(),
+            sources: sources(error).map(|e| e.to_string()).collect(),
+            type_name: Some(std::any::type_name::<E>()),
+            backtrace: None,
+        }
+    }
+
+    /// The report as a value that can be recorded as a field.
+    pub fn as_value(&self) -> &(dyn Error + 'static) {
+        self
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<&anyhow::Error> for ErrorReport {
+    fn from(error: &anyhow::Error) -> Self {
+        let backtrace = error.backtrace();
+        Self {
+            message: error.to_string(),
+            sources: error.chain().skip(1).map(|e| e.to_string()).collect(),
+            type_name: None,
+            backtrace: (backtrace.status() == std::backtrace::BacktraceStatus::Captured)
+                .then(|| backtrace.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// The sources are already in the report as text, so they aren't exposed again through `source`.
+impl Error for ErrorReport {}
+
+fn sources<'a>(
+    error: &'a (dyn Error + 'static),
+) -> impl Iterator<Item = &'a (dyn Error + 'static)> {
+    iter::successors(error.source(), |&e| e.source())
+}
+
+/// The value that an error recorded as a field is stored as, a backtrace is captured if
+/// `backtrace` is set and the error didn't come with one.
+pub(crate) fn error_value(error: &(dyn Error + 'static), backtrace: bool) -> Value {
+    let report = error.downcast_ref::<ErrorReport>();
+
+    let mut object = Map::new();
+    object.insert("message".into(), error.to_string().into());
+    let sources = match report {
+        Some(report) => report
+            .sources
+            .iter()
+            .map(|s| Value::from(s.as_str()))
+            .collect(),
+        None => sources(error).map(|e| Value::from(e.to_string())).collect(),
+    };
+    object.insert("sources".into(), Value::Array(sources));
+    if let Some(type_name) = report.and_then(|report| report.type_name) {
+        object.insert("type".into(), type_name.into());
+    }
+
+    let backtrace = report
+        .and_then(|report| report.backtrace.clone())
+        .or_else(|| backtrace.then(|| Backtrace::force_capture().to_string()));
+    if let Some(backtrace) = backtrace {
+        object.insert("backtrace".into(), backtrace.into());
+    }
+
+    Value::Object(object)
+}
@@ -0,0 +1,582 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Deref;
+use std::slice;
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::Value;
+use smallvec::SmallVec;
+use tracing_core::field::Field;
+
+use crate::fmt::WriteAdaptor;
+
+#[cfg(all(tracing_unstable, feature = "valuable"))]
+mod valuable;
+
+#[cfg(all(tracing_unstable, feature = "valuable"))]
+pub(crate) use self::valuable::valuable_to_json;
+#[cfg(all(tracing_unstable, feature = "valuable"))]
+pub use self::valuable::ValueLimits;
+
+// Most spans and events have a handful of fields, and most string values are short, so these
+// are kept inline to avoid allocating for each one.
+const INLINE_FIELDS: usize = 8;
+const INLINE_STR: usize = 30;
+
+/// The fields of a span or event, in the order they were first recorded.
+///
+/// Lookups are linear, which is faster than a map for the few fields spans and events have.
+/// Inserting a key that is already present replaces its value without moving it.
+#[derive(Clone, Debug, Default)]
+pub struct FieldMap<'a> {
+    entries: SmallVec<[(Cow<'a, str>, FieldValue); INLINE_FIELDS]>,
+}
+
+impl<'a> FieldMap<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&FieldValue> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut FieldValue> {
+        self.entries
+            .iter_mut()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Sets the value of a field, returning the previous one if there was one.
+    pub fn insert(
+        &mut self,
+        key: impl Into<Cow<'a, str>>,
+        value: impl Into<FieldValue>,
+    ) -> Option<FieldValue> {
+        let key = key.into();
+        let value = value.into();
+        match self.get_mut(&key) {
+            Some(existing) => Some(std::mem::replace(existing, value)),
+            None => {
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<FieldValue> {
+        let i = self.entries.iter().position(|(k, _)| k == key)?;
+        Some(self.entries.remove(i).1)
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &mut FieldValue) -> bool) {
+        self.entries.retain(|(k, v)| f(k, v));
+    }
+
+    /// Moves all the fields of `other` into this map, replacing the values of existing keys.
+    pub fn append(&mut self, other: &mut FieldMap<'a>) {
+        for (key, value) in other.entries.drain(..) {
+            self.insert(key, value);
+        }
+    }
+
+    /// Sorts the fields by key, keeping the order of keys that compare equal.
+    pub fn sort_by(&mut self, mut compare: impl FnMut(&str, &str) -> Ordering) {
+        self.entries.sort_by(|(a, _), (b, _)| compare(a, b));
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a> {
+        Iter(self.entries.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Cow<'a, str>> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &FieldValue> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+pub struct Iter<'b, 'a>(slice::Iter<'b, (Cow<'a, str>, FieldValue)>);
+
+impl<'b, 'a> Iterator for Iter<'b, 'a> {
+    type Item = (&'b Cow<'a, str>, &'b FieldValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, v)| (k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'b, 'a> IntoIterator for &'b FieldMap<'a> {
+    type Item = (&'b Cow<'a, str>, &'b FieldValue);
+    type IntoIter = Iter<'b, 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Serialize for FieldMap<'_> {
+    fn serialize<M: Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// The order the fields of each span and event are written in.
+///
+/// In declaration order, fields that aren't declared at the callsite, e.g. values stored with
+/// `CompatSpanExt`, come after the declared ones, in the order they were recorded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FieldOrder {
+    /// The order they are declared in at the callsite, e.g. `info!(url, status)` is `url` then
+    /// `status`, even for span fields that are declared `Empty` and recorded later.
+    Declaration,
+    /// The order they were first recorded in, span fields recorded after the span was created
+    /// come last.
+    Recorded,
+    /// Sorted by name, whether they're declared at the callsite or not.
+    #[default]
+    Alphabetical,
+}
+
+impl FieldOrder {
+    pub(crate) fn sort(self, declared: impl IntoIterator<Item = Field>, fields: &mut FieldMap<'_>) {
+        match self {
+            FieldOrder::Recorded => {}
+            FieldOrder::Alphabetical => fields.sort_by(|a, b| a.cmp(b)),
+            FieldOrder::Declaration => {
+                let declared: SmallVec<[&str; INLINE_FIELDS]> =
+                    declared.into_iter().map(|field| field.name()).collect();
+                let position = |key: &str| {
+                    declared
+                        .iter()
+                        .position(|name| *name == key || name.strip_prefix("r#") == Some(key))
+                        .unwrap_or(usize::MAX)
+                };
+                fields.sort_by(|a, b| position(a).cmp(&position(b)));
+            }
+        }
+    }
+}
+
+/// How numbers that JSON can't represent, or that JavaScript would read wrong, are written.
+///
+/// This applies to fields as they're recorded and to values stored with `CompatSpanExt`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Numbers {
+    /// What NaN and infinite floats become.
+    pub non_finite: NonFinite,
+    /// Writes integers that JavaScript can't represent exactly, those beyond ±(2^53 - 1), as
+    /// strings so that e.g. ids aren't rounded.
+    pub js_safe_integers: bool,
+}
+
+/// What NaN and infinite floats are written as, JSON has no way of writing them as numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFinite {
+    /// `null`, which is what `serde_json` writes them as.
+    #[default]
+    Null,
+    /// `"NaN"`, `"Infinity"` or `"-Infinity"`, like JavaScript's `String(n)`.
+    String,
+    /// The field is left out.
+    Drop,
+}
+
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+impl Numbers {
+    pub(crate) fn apply(self, fields: &mut FieldMap<'_>) {
+        fields.retain(|_, value| self.apply_value(value));
+    }
+
+    // Returns false if the field should be dropped.
+    fn apply_value(self, value: &mut FieldValue) -> bool {
+        match *value {
+            FieldValue::F64(n) if !n.is_finite() => match self.non_finite {
+                NonFinite::Null => *value = FieldValue::Json(Value::Null),
+                NonFinite::String => {
+                    let text = match n {
+                        n if n.is_nan() => "NaN",
+                        n if n > 0.0 => "Infinity",
+                        _ => "-Infinity",
+                    };
+                    *value = text.into();
+                }
+                NonFinite::Drop => return false,
+            },
+            FieldValue::Json(ref mut json) if self.js_safe_integers => stringify_unsafe(json),
+            _ if self.js_safe_integers && is_unsafe_integer(value) => {
+                *value = value.to_string().into();
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+fn is_unsafe_integer(value: &FieldValue) -> bool {
+    match *value {
+        FieldValue::I64(n) => n.unsigned_abs() > MAX_SAFE_INTEGER,
+        FieldValue::U64(n) => n > MAX_SAFE_INTEGER,
+        FieldValue::I128(_) | FieldValue::U128(_) => true,
+        _ => false,
+    }
+}
+
+// Integers nested in JSON values, e.g. of stored values, are held to the same limit.
+fn stringify_unsafe(json: &mut Value) {
+    match json {
+        Value::Number(n) => {
+            let magnitude = n.as_u64().or_else(|| n.as_i64().map(i64::unsigned_abs));
+            if magnitude.is_some_and(|m| m > MAX_SAFE_INTEGER) {
+                *json = Value::String(n.to_string());
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(stringify_unsafe),
+        Value::Object(map) => map.values_mut().for_each(stringify_unsafe),
+        _ => {}
+    }
+}
+
+/// The value of a field.
+///
+/// Values recorded by `tracing` use the typed variants, `Json` holds anything else, e.g. the
+/// arrays and objects stored with `CompatSpanExt`. Converting from a `serde_json::Value` only
+/// uses `Json` for nulls, arrays and objects.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    /// Only used for integers that don't fit in an `i64` or `u64`.
+    I128(i128),
+    /// Only used for integers that don't fit in an `i64` or `u64`.
+    U128(u128),
+    F64(f64),
+    Str(FieldStr),
+    Json(Value),
+}
+
+impl FieldValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FieldValue::Str(s) => Some(s.as_str()),
+            FieldValue::Json(value) => value.as_str(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            FieldValue::Bool(b) => Some(*b),
+            FieldValue::Json(value) => value.as_bool(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            FieldValue::I64(n) => Some(n),
+            FieldValue::U64(n) => i64::try_from(n).ok(),
+            FieldValue::I128(n) => i64::try_from(n).ok(),
+            FieldValue::U128(n) => i64::try_from(n).ok(),
+            FieldValue::Json(ref value) => value.as_i64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            FieldValue::I64(n) => u64::try_from(n).ok(),
+            FieldValue::U64(n) => Some(n),
+            FieldValue::I128(n) => u64::try_from(n).ok(),
+            FieldValue::U128(n) => u64::try_from(n).ok(),
+            FieldValue::Json(ref value) => value.as_u64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            FieldValue::I64(n) => Some(n as f64),
+            FieldValue::U64(n) => Some(n as f64),
+            FieldValue::I128(n) => Some(n as f64),
+            FieldValue::U128(n) => Some(n as f64),
+            FieldValue::F64(n) => Some(n),
+            FieldValue::Json(ref value) => value.as_f64(),
+            _ => None,
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        match self {
+            FieldValue::I64(_)
+            | FieldValue::U64(_)
+            | FieldValue::I128(_)
+            | FieldValue::U128(_)
+            | FieldValue::F64(_) => true,
+            FieldValue::Json(value) => value.is_number(),
+            _ => false,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, FieldValue::Json(Value::Null))
+    }
+
+    /// The value as JSON, integers that don't fit in a `serde_json::Value` become strings.
+    pub fn to_json(&self) -> Value {
+        match self {
+            FieldValue::Bool(b) => Value::from(*b),
+            FieldValue::I64(n) => Value::from(*n),
+            FieldValue::U64(n) => Value::from(*n),
+            FieldValue::I128(n) => Value::from(n.to_string()),
+            FieldValue::U128(n) => Value::from(n.to_string()),
+            FieldValue::F64(n) => Value::from(*n),
+            FieldValue::Str(s) => Value::from(s.as_str()),
+            FieldValue::Json(value) => value.clone(),
+        }
+    }
+}
+
+impl Serialize for FieldValue {
+    fn serialize<M: Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        match self {
+            FieldValue::Bool(b) => serializer.serialize_bool(*b),
+            FieldValue::I64(n) => serializer.serialize_i64(*n),
+            FieldValue::U64(n) => serializer.serialize_u64(*n),
+            // MessagePack and CBOR can't be decoded into JSON with 128-bit integers in them, so
+            // they get the strings of `to_json`.
+            FieldValue::I128(n) if !serializer.is_human_readable() => serializer.collect_str(n),
+            FieldValue::U128(n) if !serializer.is_human_readable() => serializer.collect_str(n),
+            FieldValue::I128(n) => serializer.serialize_i128(*n),
+            FieldValue::U128(n) => serializer.serialize_u128(*n),
+            FieldValue::F64(n) => serializer.serialize_f64(*n),
+            FieldValue::Str(s) => serializer.serialize_str(s),
+            FieldValue::Json(value) => value.serialize(serializer),
+        }
+    }
+}
+
+/// Writes the value as JSON, like `serde_json::Value` does.
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        serde_json::to_writer(WriteAdaptor(f), self).map_err(|_| fmt::Error)
+    }
+}
+
+impl From<Value> for FieldValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Bool(b) => FieldValue::Bool(b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+                (Some(n), _, _) => FieldValue::U64(n),
+                (_, Some(n), _) => FieldValue::I64(n),
+                (_, _, Some(n)) => FieldValue::F64(n),
+                _ => FieldValue::Json(Value::Number(n)),
+            },
+            Value::String(s) => FieldValue::Str(s.into()),
+            other => FieldValue::Json(other),
+        }
+    }
+}
+
+impl From<FieldValue> for Value {
+    fn from(value: FieldValue) -> Self {
+        match value {
+            FieldValue::Str(s) => Value::String(s.into()),
+            FieldValue::Json(value) => value,
+            other => other.to_json(),
+        }
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(b: bool) -> Self {
+        FieldValue::Bool(b)
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(n: i64) -> Self {
+        FieldValue::I64(n)
+    }
+}
+
+impl From<u64> for FieldValue {
+    fn from(n: u64) -> Self {
+        FieldValue::U64(n)
+    }
+}
+
+impl From<i128> for FieldValue {
+    fn from(n: i128) -> Self {
+        match i64::try_from(n) {
+            Ok(n) => FieldValue::I64(n),
+            Err(_) => u64::try_from(n).map_or(FieldValue::I128(n), FieldValue::U64),
+        }
+    }
+}
+
+impl From<u128> for FieldValue {
+    fn from(n: u128) -> Self {
+        u64::try_from(n).map_or(FieldValue::U128(n), FieldValue::U64)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(n: f64) -> Self {
+        FieldValue::F64(n)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(s: &str) -> Self {
+        FieldValue::Str(s.into())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(s: String) -> Self {
+        FieldValue::Str(s.into())
+    }
+}
+
+/// A string that is stored inline, without allocating, when it's short.
+///
+/// It can be written to with `fmt::Write`, e.g. to record `Debug` values, and only moves to the
+/// heap once it outgrows the inline buffer.
+#[derive(Clone)]
+pub struct FieldStr(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Inline { len: u8, bytes: [u8; INLINE_STR] },
+    Heap(String),
+}
+
+impl FieldStr {
+    pub fn new() -> Self {
+        FieldStr(Repr::Inline {
+            len: 0,
+            bytes: [0; INLINE_STR],
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            // SAFETY: the inline bytes are only written by `push_str`, which copies in whole
+            // `str`s, so the first `len` bytes are always valid UTF-8.
+            Repr::Inline { len, bytes } => unsafe {
+                std::str::from_utf8_unchecked(&bytes[..*len as usize])
+            },
+            Repr::Heap(s) => s,
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        match &mut self.0 {
+            Repr::Inline { len, bytes } if *len as usize + s.len() <= INLINE_STR => {
+                let start = *len as usize;
+                bytes[start..start + s.len()].copy_from_slice(s.as_bytes());
+                *len += s.len() as u8;
+            }
+            Repr::Inline { .. } => {
+                let mut heap = String::with_capacity(self.len() + s.len());
+                heap.push_str(self);
+                heap.push_str(s);
+                self.0 = Repr::Heap(heap);
+            }
+            Repr::Heap(heap) => heap.push_str(s),
+        }
+    }
+}
+
+impl Default for FieldStr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for FieldStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Write for FieldStr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FieldStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for FieldStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl PartialEq for FieldStr {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for FieldStr {}
+
+impl From<&str> for FieldStr {
+    fn from(s: &str) -> Self {
+        let mut field = FieldStr::new();
+        field.push_str(s);
+        field
+    }
+}
+
+impl From<String> for FieldStr {
+    fn from(s: String) -> Self {
+        // Keep the allocation that was already made.
+        FieldStr(Repr::Heap(s))
+    }
+}
+
+impl From<FieldStr> for String {
+    fn from(s: FieldStr) -> Self {
+        match s.0 {
+            Repr::Inline { .. } => s.as_str().to_string(),
+            Repr::Heap(s) => s,
+        }
+    }
+}
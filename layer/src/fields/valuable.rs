@@ -0,0 +1,203 @@
+use ::valuable::{Enumerable, Fields, NamedValues, Valuable, Value as Valued, Visit};
+use serde_json::{Map, Value};
+
+use crate::error::error_value;
+
+const TRUNCATED: &str = "...";
+
+/// Limits on the JSON a single `valuable` field turns into, so that a huge or deeply nested value
+/// doesn't make for a huge log line. Whatever is past a limit is replaced by `"..."`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueLimits {
+    /// How deeply lists, maps, structs and enum variants can be nested, 8 by default.
+    pub max_depth: usize,
+    /// How many values a field can have in all, counting those nested in others, 1000 by default.
+    pub max_values: usize,
+    /// How many bytes of each string are kept, 4096 by default. Longer strings are cut at a
+    /// character boundary and end with `...`.
+    pub max_string_len: usize,
+}
+
+impl Default for ValueLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            max_values: 1000,
+            max_string_len: 4096,
+        }
+    }
+}
+
+// The limits, and how many more values are allowed.
+struct Budget {
+    limits: ValueLimits,
+    values: usize,
+}
+
+/// Converts a `valuable` value to JSON the way serde would: structs and maps become objects,
+/// lists and tuples arrays, and enum variants with fields are tagged with the variant's name.
+pub(crate) fn valuable_to_json(value: Valued<'_>, limits: ValueLimits) -> Value {
+    let mut budget = Budget {
+        limits,
+        values: limits.max_values,
+    };
+    convert(value, 0, &mut budget)
+}
+
+fn convert(value: Valued<'_>, depth: usize, budget: &mut Budget) -> Value {
+    budget.values = budget.values.saturating_sub(1);
+
+    match value {
+        Valued::Bool(b) => b.into(),
+        Valued::Char(c) => c.to_string().into(),
+        Valued::F32(n) => n.into(),
+        Valued::F64(n) => n.into(),
+        Valued::I8(n) => n.into(),
+        Valued::I16(n) => n.into(),
+        Valued::I32(n) => n.into(),
+        Valued::I64(n) => n.into(),
+        Valued::I128(n) => i64::try_from(n).map_or_else(|_| n.to_string().into(), Value::from),
+        Valued::Isize(n) => n.into(),
+        Valued::String(s) => truncate(s, budget.limits.max_string_len),
+        Valued::U8(n) => n.into(),
+        Valued::U16(n) => n.into(),
+        Valued::U32(n) => n.into(),
+        Valued::U64(n) => n.into(),
+        Valued::U128(n) => u64::try_from(n).map_or_else(|_| n.to_string().into(), Value::from),
+        Valued::Usize(n) => n.into(),
+        Valued::Path(path) => truncate(&path.to_string_lossy(), budget.limits.max_string_len),
+        Valued::Error(error) => error_value(error, false),
+        Valued::Unit => Value::Null,
+        _ if depth >= budget.limits.max_depth => TRUNCATED.into(),
+        Valued::Listable(list) => collect(list, depth, budget).array(),
+        Valued::Tuplable(tuple) => collect(tuple, depth, budget).array(),
+        Valued::Mappable(map) => collect(map, depth, budget).object(),
+        Valued::Structable(s) => {
+            let fields = collect(s, depth, budget);
+            match s.definition().fields() {
+                Fields::Named(_) => fields.object(),
+                Fields::Unnamed(_) => fields.array(),
+            }
+        }
+        Valued::Enumerable(e) => enum_to_json(e, depth, budget),
+        other => truncate(&format!("{:?}", other), budget.limits.max_string_len),
+    }
+}
+
+fn truncate(s: &str, max_len: usize) -> Value {
+    if s.len() <= max_len {
+        return s.into();
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &s[..end], TRUNCATED).into()
+}
+
+// Like serde, unit variants are their name, newtype variants `{"Name": value}`, and other
+// variants `{"Name": [values]}` or `{"Name": {fields}}`.
+fn enum_to_json(e: &dyn Enumerable, depth: usize, budget: &mut Budget) -> Value {
+    let variant = e.variant();
+    let fields = collect(e, depth, budget);
+    let value = match variant.fields() {
+        Fields::Unnamed(0) => return variant.name().into(),
+        Fields::Unnamed(1) if fields.list.len() == 1 => {
+            fields.list.into_iter().next().unwrap_or_default()
+        }
+        Fields::Unnamed(_) => fields.array(),
+        Fields::Named(_) => fields.object(),
+    };
+
+    let mut object = Map::new();
+    object.insert(variant.name().to_string(), value);
+    Value::Object(object)
+}
+
+fn collect<'b>(value: &dyn Valuable, depth: usize, budget: &'b mut Budget) -> Collect<'b> {
+    let mut collect = Collect {
+        depth: depth + 1,
+        budget,
+        list: Vec::new(),
+        map: Map::new(),
+        truncated: false,
+    };
+    value.visit(&mut collect);
+    collect
+}
+
+// Gathers the values of a list, map, struct or enum variant.
+struct Collect<'b> {
+    depth: usize,
+    budget: &'b mut Budget,
+    list: Vec<Value>,
+    map: Map<String, Value>,
+    truncated: bool,
+}
+
+impl Collect<'_> {
+    fn array(self) -> Value {
+        Value::Array(self.list)
+    }
+
+    fn object(self) -> Value {
+        Value::Object(self.map)
+    }
+
+    // Whether the budget allows for another value, marking the collection as truncated the first
+    // time it doesn't.
+    fn has_room(&mut self) -> bool {
+        if self.budget.values > 0 {
+            return true;
+        }
+        if !self.truncated {
+            self.truncated = true;
+            self.list.push(TRUNCATED.into());
+            self.map.insert(TRUNCATED.into(), TRUNCATED.into());
+        }
+        false
+    }
+
+    fn push(&mut self, value: Valued<'_>) {
+        if self.has_room() {
+            let value = convert(value, self.depth, self.budget);
+            self.list.push(value);
+        }
+    }
+
+    fn insert(&mut self, key: String, value: Valued<'_>) {
+        if self.has_room() {
+            let value = convert(value, self.depth, self.budget);
+            self.map.insert(key, value);
+        }
+    }
+}
+
+impl Visit for Collect<'_> {
+    fn visit_value(&mut self, value: Valued<'_>) {
+        self.push(value);
+    }
+
+    fn visit_named_fields(&mut self, named_values: &NamedValues<'_>) {
+        for (field, value) in named_values {
+            self.insert(field.name().to_string(), *value);
+        }
+    }
+
+    fn visit_unnamed_fields(&mut self, values: &[Valued<'_>]) {
+        for value in values {
+            self.push(*value);
+        }
+    }
+
+    fn visit_entry(&mut self, key: Valued<'_>, value: Valued<'_>) {
+        let key = match key {
+            Valued::String(key) => key.to_string(),
+            key => match convert(key, self.depth, self.budget) {
+                Value::String(key) => key,
+                key => key.to_string(),
+            },
+        };
+        self.insert(key, value);
+    }
+}
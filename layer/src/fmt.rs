@@ -1,26 +1,267 @@
+pub mod binary;
+pub mod bunyan;
+pub mod ecs;
+pub mod event_format;
+pub mod gelf;
 pub mod json;
+pub mod logfmt;
+pub mod otlp;
+pub mod pretty;
+pub mod time;
 
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt;
-use std::io;
+use std::io::{self, IsTerminal};
 use std::time::Duration;
 
+use smallvec::SmallVec;
+use tracing_core::span::{Attributes, Id, Record};
 use tracing_core::{Event, Subscriber};
+use tracing_subscriber::fmt::format::FormatFields;
+use tracing_subscriber::fmt::FmtContext;
 use tracing_subscriber::layer::Context;
-use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::registry::{Extensions, LookupSpan, SpanRef};
+
+use crate::compat_layer::{FieldOptions, Visitor};
+use crate::fields::FieldValue;
+use crate::span_events::SpanEvents;
 
 pub trait Format<S>
 where
     S: Subscriber + for<'lookup> LookupSpan<'lookup>,
 {
+    /// Whether the events are text written by `format_event`. Binary formats only write events
+    /// with `format_event_bytes`, so they can only be used with `CompatLayer`, using one with
+    /// [`AsFormatEvent`](event_format::AsFormatEvent) doesn't compile.
+    const TEXT: bool = true;
+
     fn format_event<W: fmt::Write>(
         &self,
         event: &Event<'_>,
-        ctx: Context<'_, S>,
+        ctx: &dyn FormatContext<S>,
         writer: W,
     ) -> fmt::Result;
+
+    /// Formats the event as bytes appended to `buf`, which is how `CompatLayer` formats events.
+    /// By default this is the text written by `format_event`, binary formats override it.
+    fn format_event_bytes(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        self.format_event(event, ctx, BytesWriter(buf))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /// Called when a span is created, for formatters that need to keep state of their own in the
+    /// span's extensions.
+    fn on_new_span(&self, _attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {}
+
+    /// Called when fields are recorded on a span after it was created.
+    fn on_record(&self, _id: &Id, _values: &Record<'_>, _ctx: Context<'_, S>) {}
+}
+
+/// The span lookups that a [`Format`] can make while formatting an event.
+///
+/// This is implemented for the `Context` given to a `Layer` as well as the `FmtContext` given to
+/// a `FormatEvent`, so that a formatter can be used by `CompatLayer` or by
+/// `tracing_subscriber::fmt::Layer`.
+pub trait FormatContext<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn span(&self, id: &Id) -> Option<SpanRef<'_, S>>;
+
+    fn lookup_current(&self) -> Option<SpanRef<'_, S>>;
+
+    /// The `Context` of the layer that is formatting, if any.
+    fn layer_context(&self) -> Option<Context<'_, S>> {
+        None
+    }
+
+    /// Records the fields of the event, applying the redaction, field order and handling of
+    /// numbers configured on the layer.
+    fn record_event(&self, event: &Event<'_>) -> Visitor<'static> {
+        let mut visitor = Visitor::default();
+        event.record(&mut visitor);
+        FieldOptions::default().apply(event.fields(), visitor.fields_mut());
+        visitor
+    }
+
+    /// The point in the lifecycle of a span that the event was emitted for, if it's one of the
+    /// span events of `CompatLayer`.
+    fn span_event(&self) -> Option<SpanEvents> {
+        None
+    }
+
+    /// Whether the layer lets formatters color the output, if it has a say.
+    fn ansi(&self) -> Option<bool> {
+        None
+    }
+
+    /// The explicit parent of the event if it has one, otherwise the current span.
+    fn event_span(&self, event: &Event<'_>) -> Option<SpanRef<'_, S>> {
+        event
+            .parent()
+            .and_then(|id| self.span(id))
+            .or_else(|| self.lookup_current())
+    }
 }
 
-struct WriteAdaptor<'a, W>(&'a mut W)
+impl<S> FormatContext<S> for Context<'_, S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn span(&self, id: &Id) -> Option<SpanRef<'_, S>> {
+        Context::span(self, id)
+    }
+
+    fn lookup_current(&self) -> Option<SpanRef<'_, S>> {
+        Context::lookup_current(self)
+    }
+
+    fn layer_context(&self) -> Option<Context<'_, S>> {
+        Some(self.clone())
+    }
+}
+
+impl<S, N> FormatContext<S> for FmtContext<'_, S, N>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn span(&self, id: &Id) -> Option<SpanRef<'_, S>> {
+        FmtContext::span(self, id)
+    }
+
+    fn lookup_current(&self) -> Option<SpanRef<'_, S>> {
+        FmtContext::lookup_current(self)
+    }
+}
+
+/// How to resolve a field that appears on more than one span, or on a span and the event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// The value from the event, or the span closest to it, wins.
+    #[default]
+    InnermostWins,
+    /// The value from the span closest to the root wins.
+    OutermostWins,
+    /// Span fields are prefixed with the name of their span, e.g. `get_cat.id`. If spans with the
+    /// same name are nested the innermost one wins.
+    Prefix,
+    /// Span fields are nested under a `spans` list, from the root to the current span.
+    Nest,
+}
+
+/// The fields of each span in the scope of an event, from the root, as (span name, visitor).
+pub(crate) type SpanFields<'a> = [(&'static str, &'a Visitor<'static>)];
+
+/// Holds something for each span in a scope, inline for all but unusually deep ones.
+pub(crate) type ScopeVec<T> = SmallVec<[T; 16]>;
+
+/// Calls `f` with the fields of the span and each of its ancestors, from the root.
+pub(crate) fn with_span_fields<S, R>(
+    span: Option<SpanRef<'_, S>>,
+    f: impl FnOnce(&SpanFields<'_>) -> R,
+) -> R
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    with_span_scope(span, |spans, _| f(spans))
+}
+
+/// Like `with_span_fields`, `f` is also given the extensions of the span itself.
+pub(crate) fn with_span_scope<S, R>(
+    span: Option<SpanRef<'_, S>>,
+    f: impl FnOnce(&SpanFields<'_>, Option<&Extensions<'_>>) -> R,
+) -> R
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    let spans: ScopeVec<_> = span
+        .map(|span| span.scope().from_root().collect())
+        .unwrap_or_default();
+    let extensions: ScopeVec<_> = spans.iter().map(|span| span.extensions()).collect();
+    // Spans only have a visitor when `CompatLayer` or `FieldRecorder` is installed, a formatter
+    // used without either just doesn't see span fields.
+    let visitors: ScopeVec<_> = spans
+        .iter()
+        .zip(&extensions)
+        .filter_map(|(span, extensions)| {
+            let visitor = extensions.get::<Visitor>()?;
+            Some((span.metadata().name(), visitor))
+        })
+        .collect();
+
+    f(&visitors, extensions.last())
+}
+
+/// Flattens span and event fields into a list of entries without duplicate keys, in the order
+/// they were found in, according to the policy. `Nest` is treated like `InnermostWins`, formatters
+/// that support it need to handle it themselves.
+pub(crate) fn merge_fields<'a>(
+    policy: CollisionPolicy,
+    spans: &SpanFields<'a>,
+    event: &'a Visitor<'_>,
+) -> Vec<(Cow<'a, str>, &'a FieldValue)> {
+    let mut entries = Vec::new();
+
+    for (name, visitor) in spans {
+        for (key, value) in visitor.fields() {
+            let key = match policy {
+                CollisionPolicy::Prefix => Cow::Owned(format!("{}.{}", name, key)),
+                _ => Cow::Borrowed(key.as_ref()),
+            };
+            entries.push((key, value));
+        }
+    }
+    for (key, value) in event.fields() {
+        entries.push((Cow::Borrowed(key.as_ref()), value));
+    }
+
+    // Find which occurrence of each key to keep before dropping the rest so that the order of the
+    // remaining entries is preserved.
+    let mut keep = vec![false; entries.len()];
+    let mut seen = HashSet::new();
+    let mut mark = |i: usize| keep[i] = seen.insert(entries[i].0.as_ref());
+    match policy {
+        CollisionPolicy::OutermostWins => (0..entries.len()).for_each(&mut mark),
+        _ => (0..entries.len()).rev().for_each(&mut mark),
+    }
+
+    let mut keep = keep.into_iter();
+    entries.retain(|_| keep.next().unwrap_or(false));
+    entries
+}
+
+/// The value that `merge_fields` would keep for `key`, without merging all the fields. With `Nest`
+/// only the event's fields are looked at, span fields aren't written at the top level.
+pub(crate) fn resolve_field<'a>(
+    policy: CollisionPolicy,
+    spans: &SpanFields<'a>,
+    event: &'a Visitor<'_>,
+    key: &str,
+) -> Option<&'a FieldValue> {
+    let in_span = |&(name, visitor): &(&str, &'a Visitor<'static>)| match policy {
+        CollisionPolicy::Prefix => key
+            .strip_prefix(name)
+            .and_then(|key| key.strip_prefix('.'))
+            .and_then(|key| visitor.fields().get(key)),
+        CollisionPolicy::Nest => None,
+        _ => visitor.fields().get(key),
+    };
+
+    let in_event = event.fields().get(key);
+    match policy {
+        CollisionPolicy::OutermostWins => spans.iter().find_map(in_span).or(in_event),
+        _ => in_event.or_else(|| spans.iter().rev().find_map(in_span)),
+    }
+}
+
+pub(crate) struct WriteAdaptor<'a, W>(pub(crate) &'a mut W)
 where
     W: fmt::Write;
 
@@ -36,7 +277,7 @@ where
             .write_str(s)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
-        Ok(s.as_bytes().len())
+        Ok(s.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -44,6 +285,23 @@ where
     }
 }
 
+// Appends text to a byte buffer, the text is already valid UTF-8 so there's nothing to check.
+struct BytesWriter<'a>(&'a mut Vec<u8>);
+
+impl fmt::Write for BytesWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+// Whether to color output written to `stream`: if it's a terminal and the `NO_COLOR` environment
+// variable isn't set.
+pub(crate) fn ansi_for(stream: &impl IsTerminal) -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    stream.is_terminal() && !no_color
+}
+
 pub fn format_duration(duration: Duration) -> String {
     let secs_part = match duration.as_secs().checked_mul(1_000_000_000) {
         Some(v) => v,
@@ -74,3 +332,31 @@ pub fn format_duration(duration: Duration) -> String {
         format_fraction(duration_in_ns as f64 / 1_000_000_000.0, "s")
     }
 }
+
+/// Parses the text written by `format_duration` back into a `Duration`, for tooling that reads
+/// existing logs. `format_duration` keeps four significant digits, so this is only exact for
+/// durations under a microsecond or in whole seconds.
+///
+/// Returns `None` if the text isn't a non-negative number followed by `ns`, `us`, `µs`, `ms` or
+/// `s`.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let (number, nanos_per_unit) = [
+        ("ns", 1),
+        ("us", 1_000),
+        ("µs", 1_000),
+        ("ms", 1_000_000),
+        ("s", 1_000_000_000),
+    ]
+    .into_iter()
+    .find_map(|(suffix, nanos)| Some((text.strip_suffix(suffix)?, nanos)))?;
+
+    if let Ok(whole) = number.parse::<u64>() {
+        let secs = whole / (1_000_000_000 / nanos_per_unit);
+        let nanos = whole % (1_000_000_000 / nanos_per_unit) * nanos_per_unit;
+        return Some(Duration::new(secs, nanos as u32));
+    }
+
+    let value = number.parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(value * nanos_per_unit as f64 / 1e9).ok()
+}
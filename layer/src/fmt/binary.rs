@@ -0,0 +1,215 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use serde::Serialize;
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+
+use super::json::JsonFormatter;
+use super::{Format, FormatContext};
+
+/// The encoding of the records written by `BinaryFormatter`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    MessagePack,
+    Cbor,
+}
+
+/// Formats events as the same record as `JsonFormatter`, encoded as MessagePack or CBOR, which
+/// avoids escaping strings for services that log a lot.
+///
+/// Each record is framed by its length as a big-endian `u32`, so a stream of them can be read
+/// back with [`Decoder`]. The output isn't text, so this can only be used with `CompatLayer`,
+/// which writes events with `format_event_bytes`. `format_event` always returns an error, and
+/// using the formatter with `AsFormatEvent` doesn't compile:
+///
+/// ```compile_fail
+/// use layer::fmt::binary::{BinaryFormatter, Encoding};
+/// use layer::fmt::event_format::AsFormatEvent;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let formatter = BinaryFormatter::new(Encoding::Cbor);
+/// let subscriber = tracing_subscriber::registry()
+///     .with(tracing_subscriber::fmt::layer().event_format(AsFormatEvent::new(formatter)));
+/// tracing::subscriber::set_global_default(subscriber).unwrap();
+/// ```
+///
+/// Integers that don't fit in 64 bits are written as strings, which the decoders of both encodings
+/// can read.
+pub struct BinaryFormatter<S> {
+    json: JsonFormatter<S>,
+    encoding: Encoding,
+}
+
+impl<S> BinaryFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            json: JsonFormatter::new(),
+            encoding,
+        }
+    }
+
+    /// Takes the fields of records from a configured `JsonFormatter`, e.g. to change the
+    /// timestamp or collision policy.
+    pub fn with_json_formatter(mut self, json: JsonFormatter<S>) -> Self {
+        self.json = json;
+        self
+    }
+}
+
+struct Record<'a, S> {
+    json: &'a JsonFormatter<S>,
+    event: &'a Event<'a>,
+    ctx: &'a dyn FormatContext<S>,
+}
+
+impl<S> Serialize for Record<'_, S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn serialize<M: serde::Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        self.json.serialize_record(serializer, self.event, self.ctx)
+    }
+}
+
+impl<S> Format<S> for BinaryFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    const TEXT: bool = false;
+
+    fn format_event<W: fmt::Write>(
+        &self,
+        _event: &Event<'_>,
+        _ctx: &dyn FormatContext<S>,
+        _writer: W,
+    ) -> fmt::Result {
+        Err(fmt::Error)
+    }
+
+    fn format_event_bytes(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let start = buf.len();
+        buf.extend_from_slice(&[0; 4]);
+
+        let record = Record {
+            json: &self.json,
+            event,
+            ctx,
+        };
+        let result = match self.encoding {
+            Encoding::MessagePack => rmp_serde::encode::write(buf, &record)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+            Encoding::Cbor => ciborium::ser::into_writer(&record, &mut *buf)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+        };
+        let len = result.and_then(|()| {
+            u32::try_from(buf.len() - start - 4)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "record is too long"))
+        });
+
+        match len {
+            Ok(len) => {
+                buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+                Ok(())
+            }
+            Err(e) => {
+                buf.truncate(start);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Reads the records written by `BinaryFormatter` back, e.g. to turn a stream of them into JSON
+/// lines for people to read.
+///
+/// ```
+/// use layer::fmt::binary::{Decoder, Encoding};
+///
+/// let stream: &[u8] = &[0, 0, 0, 8, 0x81, 0xa5, b't', b'i', b't', b'l', b'e', 0xc0];
+/// let mut lines = Vec::new();
+/// Decoder::new(stream, Encoding::MessagePack).write_json_lines(&mut lines).unwrap();
+/// assert_eq!(lines, b"{\"title\":null}\n");
+/// ```
+pub struct Decoder<R> {
+    reader: R,
+    encoding: Encoding,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(reader: R, encoding: Encoding) -> Self {
+        Self { reader, encoding }
+    }
+
+    /// Writes each record as a line of JSON, and returns how many records were skipped because
+    /// they couldn't be decoded.
+    ///
+    /// Records are framed by their length, so the ones after a bad record can still be read. A
+    /// stream that ends in the middle of a record is an error.
+    pub fn write_json_lines<W: io::Write>(self, mut writer: W) -> io::Result<u64> {
+        let mut skipped = 0;
+        for record in self {
+            match record {
+                Ok(record) => {
+                    serde_json::to_writer(&mut writer, &record)?;
+                    writer.write_all(b"\n")?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => skipped += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        writer.flush()?;
+        Ok(skipped)
+    }
+
+    // Reads the length of the next record, `None` at the end of the stream.
+    fn read_len(&mut self) -> io::Result<Option<u64>> {
+        let mut len = [0; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match self.reader.read(&mut len[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(u32::from_be_bytes(len).into()))
+    }
+}
+
+impl<R: Read> Iterator for Decoder<R> {
+    type Item = io::Result<serde_json::Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = match self.read_len() {
+            Ok(len) => len?,
+            Err(e) => return Some(Err(e)),
+        };
+
+        // Don't trust the length with an allocation up front, a corrupt one could be up to 4GiB.
+        let mut payload = Vec::new();
+        if let Err(e) = (&mut self.reader).take(len).read_to_end(&mut payload) {
+            return Some(Err(e));
+        }
+        if payload.len() as u64 != len {
+            return Some(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        Some(match self.encoding {
+            Encoding::MessagePack => rmp_serde::from_slice(&payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Encoding::Cbor => ciborium::de::from_reader(payload.as_slice())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        })
+    }
+}
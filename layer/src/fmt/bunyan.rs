@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::marker;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::ser::{SerializeMap, Serializer as _};
+use serde_json::ser::Serializer;
+use tracing_core::{Event, Level, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::compat_layer::Visitor;
+use crate::fields::FieldValue;
+use crate::fmt::WriteAdaptor;
+use crate::span_events::SpanEvents;
+
+use super::json::NestedSpans;
+use super::time::{Clock, SystemClock};
+use super::FormatContext;
+use super::{merge_fields, parse_duration, with_span_fields, CollisionPolicy, Format};
+
+// The keys of the entries written by the formatter itself, span and event fields with these names
+// are written with a `fields.` prefix instead, as is a field named `spans` with
+// `CollisionPolicy::Nest`.
+const RESERVED_FIELDS: &[&str] = &[
+    "v",
+    "name",
+    "msg",
+    "level",
+    "hostname",
+    "pid",
+    "time",
+    "target",
+    "line",
+    "file",
+    "span",
+    "elapsed_milliseconds",
+    #[cfg(feature = "opentelemetry")]
+    "trace_id",
+    #[cfg(feature = "opentelemetry")]
+    "span_id",
+];
+
+/// Formats events as Bunyan records so that they can be read with the `bunyan` CLI.
+///
+/// Span start events, `NEW` and `FIRST_ENTER`, have the message `[SPAN_START]` and close events
+/// have the message `[SPAN_END]` with the elapsed time in `elapsed_milliseconds`. The name of the
+/// span is in `span`.
+///
+/// Fields named like the entries Bunyan gives a meaning to, or the ones the formatter adds, e.g.
+/// `name` or `target`, are written as `fields.name`.
+pub struct BunyanFormatter<S> {
+    name: String,
+    hostname: String,
+    pid: u32,
+    clock: Box<dyn Clock>,
+    collisions: CollisionPolicy,
+    _registry: marker::PhantomData<S>,
+}
+
+impl<S> BunyanFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    /// Creates a formatter for the application called `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+            pid: std::process::id(),
+            clock: Box::new(SystemClock),
+            collisions: CollisionPolicy::default(),
+            _registry: marker::PhantomData,
+        }
+    }
+
+    /// Overrides the hostname, which is looked up from the system by default.
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Sets the clock used to timestamp events, the system clock by default.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets how fields that appear on several spans, or on a span and the event, are resolved so
+    /// that keys aren't duplicated.
+    pub fn with_collisions(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = policy;
+        self
+    }
+}
+
+// The key a field is written with.
+fn output_key(key: &str, nest: bool) -> Cow<'_, str> {
+    if RESERVED_FIELDS.contains(&key) || (nest && key == "spans") {
+        Cow::Owned(format!("fields.{}", key))
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+fn bunyan_level(level: &Level) -> u8 {
+    match *level {
+        Level::TRACE => 10,
+        Level::DEBUG => 20,
+        Level::INFO => 30,
+        Level::WARN => 40,
+        Level::ERROR => 50,
+    }
+}
+
+// The elapsed time of a close event, from whichever duration fields it has.
+fn elapsed(event: &Visitor<'_>) -> Option<Duration> {
+    let fields = event.fields();
+    if let Some(nanos) = fields.get("elapsed_ns").and_then(FieldValue::as_u64) {
+        return Some(Duration::from_nanos(nanos));
+    }
+    if let Some(millis) = fields.get("elapsed_ms").and_then(FieldValue::as_f64) {
+        return Duration::try_from_secs_f64(millis / 1000.0).ok();
+    }
+    fields
+        .get("elapsed")
+        .and_then(FieldValue::as_str)
+        .and_then(parse_duration)
+}
+
+impl<S> Format<S> for BunyanFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn format_event<W: fmt::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut writer: W,
+    ) -> fmt::Result {
+        self.write_event(event, ctx, WriteAdaptor(&mut writer))
+            .map_err(|_| fmt::Error)
+    }
+
+    fn format_event_bytes(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        self.write_event(event, ctx, buf)
+    }
+}
+
+impl<S> BunyanFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn write_event<O: io::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut out: O,
+    ) -> io::Result<()> {
+        let mut visit = || {
+            let mut serializer = Serializer::new(&mut out);
+            let mut serializer = serializer.serialize_map(None)?;
+            let mut visitor = ctx.record_event(event);
+            let metadata = event.metadata();
+
+            let current_span = ctx.event_span(event);
+
+            let message = visitor.fields_mut().remove("message");
+            let message = message
+                .as_ref()
+                .and_then(|m| m.as_str())
+                .unwrap_or(metadata.name());
+            let span_name = current_span.as_ref().map(|span| span.metadata().name());
+            let (message, span_name, elapsed) = match (ctx.span_event(), span_name) {
+                (Some(kind), Some(name)) if kind.contains(SpanEvents::CLOSE) => {
+                    ("[SPAN_END]", Some(name), elapsed(&visitor))
+                }
+                (Some(kind), Some(name))
+                    if kind.contains(SpanEvents::NEW) || kind.contains(SpanEvents::FIRST_ENTER) =>
+                {
+                    ("[SPAN_START]", Some(name), None)
+                }
+                _ => (message, None, None),
+            };
+
+            let time = DateTime::<Utc>::from(self.clock.now());
+
+            serializer.serialize_entry("v", &0)?;
+            serializer.serialize_entry("name", &self.name)?;
+            serializer.serialize_entry("msg", &message)?;
+            serializer.serialize_entry("level", &bunyan_level(metadata.level()))?;
+            serializer.serialize_entry("hostname", &self.hostname)?;
+            serializer.serialize_entry("pid", &self.pid)?;
+            serializer
+                .serialize_entry("time", &time.to_rfc3339_opts(SecondsFormat::Millis, true))?;
+            serializer.serialize_entry("target", metadata.target())?;
+            serializer.serialize_entry("line", &metadata.line())?;
+            serializer.serialize_entry("file", &metadata.file())?;
+
+            if let Some(name) = span_name {
+                serializer.serialize_entry("span", name)?;
+            }
+
+            if let Some(elapsed) = elapsed {
+                serializer.serialize_entry(
+                    "elapsed_milliseconds",
+                    &u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+                )?;
+            }
+
+            #[cfg(feature = "opentelemetry")]
+            if let Some(ids) = current_span.as_ref().and_then(crate::otel::otel_ids) {
+                serializer.serialize_entry("trace_id", &ids.trace_id)?;
+                serializer.serialize_entry("span_id", &ids.span_id)?;
+            }
+
+            with_span_fields(current_span, |spans| {
+                if self.collisions == CollisionPolicy::Nest {
+                    serializer.serialize_entry("spans", &NestedSpans(spans))?;
+                    for (key, val) in visitor.fields() {
+                        serializer.serialize_entry(&output_key(key, true), val)?;
+                    }
+                    return Ok(());
+                }
+
+                for (key, val) in merge_fields(self.collisions, spans, &visitor) {
+                    serializer.serialize_entry(&output_key(&key, false), val)?;
+                }
+                Ok(())
+            })?;
+
+            serializer.end()
+        };
+
+        visit()?;
+        out.write_all(b"\n")
+    }
+}
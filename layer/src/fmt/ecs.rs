@@ -0,0 +1,236 @@
+use std::fmt;
+use std::io;
+use std::marker;
+
+use serde_json::{json, Map, Value};
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::fmt::WriteAdaptor;
+
+use super::time::{Clock, SystemClock, TimestampFormat};
+use super::{merge_fields, with_span_fields, CollisionPolicy, Format, FormatContext};
+
+/// The version of ECS that records are written for.
+pub const ECS_VERSION: &str = "8.11.0";
+
+/// Formats events as JSON following the Elastic Common Schema, e.g.
+///
+/// ```json
+/// {"@timestamp":"2023-06-01T12:30:00.000000Z","ecs":{"version":"8.11.0"},
+///  "log":{"level":"INFO","logger":"demo::cats","origin":{"file":{"name":"src/cats.rs","line":12}}},
+///  "message":"Fetching link!","process":{"pid":42},"labels":{"correlation_id":"abc"}}
+/// ```
+///
+/// Span and event fields aren't ECS fields, they are written under a namespace of their own,
+/// `labels` by default, so that they can't clash with the schema. ECS indexes every label as a
+/// keyword, so under `labels` values are written as strings and objects are flattened, e.g.
+/// `{"user":{"id":1}}` becomes `"user_id":"1"`. Dots in the names of labels would nest them, so
+/// they're replaced with underscores.
+pub struct EcsFormatter<S> {
+    pid: u32,
+    clock: Box<dyn Clock>,
+    namespace: String,
+    collisions: CollisionPolicy,
+    _registry: marker::PhantomData<S>,
+}
+
+impl<S> Default for EcsFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> EcsFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    pub fn new() -> Self {
+        Self {
+            pid: std::process::id(),
+            clock: Box::new(SystemClock),
+            namespace: String::from("labels"),
+            collisions: CollisionPolicy::default(),
+            _registry: marker::PhantomData,
+        }
+    }
+
+    /// Sets the field that span and event fields are written under, dots nest it further, e.g.
+    /// `cats.context`. Fields keep their types under any namespace other than `labels`.
+    ///
+    /// # Panics
+    ///
+    /// If the namespace is empty, or is inside a field that the formatter writes itself, e.g.
+    /// `log`, as it would collide with the fields of the record.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        let namespace = namespace.into();
+        let root = namespace.split('.').next().unwrap_or_default();
+        assert!(
+            !namespace.split('.').any(str::is_empty) && !RECORD_FIELDS.contains(&root),
+            "`{}` can't be the namespace of fields, it collides with the fields of the record",
+            namespace
+        );
+        self.namespace = namespace;
+        self
+    }
+
+    /// Sets the clock used to timestamp events, the system clock by default.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets how fields that appear on several spans, or on a span and the event, are resolved so
+    /// that keys aren't duplicated. The namespace is flat so `Nest` is treated like `Prefix`.
+    pub fn with_collisions(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = match policy {
+            CollisionPolicy::Nest => CollisionPolicy::Prefix,
+            policy => policy,
+        };
+        self
+    }
+}
+
+// The top level fields that the formatter writes itself.
+const RECORD_FIELDS: &[&str] = &[
+    "@timestamp",
+    "ecs",
+    "log",
+    "message",
+    "process",
+    "trace",
+    "span",
+];
+
+// Inserts a field under `labels`, where every value is a keyword.
+fn insert_label(labels: &mut Map<String, Value>, key: &str, value: Value) {
+    let key = key.replace('.', "_");
+    let value = match value {
+        Value::Null => return,
+        Value::String(s) => s,
+        Value::Object(map) => {
+            for (inner, value) in map {
+                insert_label(labels, &format!("{}_{}", key, inner), value);
+            }
+            return;
+        }
+        value => value.to_string(),
+    };
+    labels.entry(key).or_insert(Value::String(value));
+}
+
+// Inserts a value at a dotted path, creating objects along the way. Values that are already
+// there win.
+fn insert_path(record: &mut Map<String, Value>, path: &str, value: Value) {
+    let mut parts = path.split('.');
+    let last = parts.next_back().unwrap_or(path);
+
+    let mut object = record;
+    for part in parts {
+        let entry = object
+            .entry(part)
+            .or_insert_with(|| Value::Object(Map::new()));
+        let Value::Object(next) = entry else {
+            return;
+        };
+        object = next;
+    }
+    object.entry(last).or_insert(value);
+}
+
+impl<S> Format<S> for EcsFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn format_event<W: fmt::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut writer: W,
+    ) -> fmt::Result {
+        self.write_event(event, ctx, WriteAdaptor(&mut writer))
+            .map_err(|_| fmt::Error)
+    }
+
+    fn format_event_bytes(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        self.write_event(event, ctx, buf)
+    }
+}
+
+impl<S> EcsFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn write_event<O: io::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut out: O,
+    ) -> io::Result<()> {
+        let mut visitor = ctx.record_event(event);
+        let metadata = event.metadata();
+
+        let current_span = ctx.event_span(event);
+
+        let message = visitor.fields_mut().remove("message");
+        let message = message
+            .as_ref()
+            .and_then(|m| m.as_str())
+            .unwrap_or(metadata.name());
+
+        let timestamp = TimestampFormat::Rfc3339Utc.format(self.clock.now());
+        let mut record = json!({
+            "@timestamp": timestamp,
+            "ecs": { "version": ECS_VERSION },
+            "log": {
+                "level": metadata.level().as_str(),
+                "logger": metadata.target(),
+            },
+            "message": message,
+            "process": { "pid": self.pid },
+        });
+        let Value::Object(record_map) = &mut record else {
+            unreachable!("the record is an object");
+        };
+
+        if let Some(file) = metadata.file() {
+            insert_path(record_map, "log.origin.file.name", file.into());
+        }
+        if let Some(line) = metadata.line() {
+            insert_path(record_map, "log.origin.file.line", line.into());
+        }
+
+        #[cfg(feature = "opentelemetry")]
+        if let Some(ids) = current_span.as_ref().and_then(crate::otel::otel_ids) {
+            insert_path(record_map, "trace.id", ids.trace_id.into());
+            insert_path(record_map, "span.id", ids.span_id.into());
+        }
+
+        let fields = with_span_fields(current_span, |spans| {
+            let mut fields = Map::new();
+            for (key, value) in merge_fields(self.collisions, spans, &visitor) {
+                match self.namespace.as_str() {
+                    "labels" => insert_label(&mut fields, &key, value.to_json()),
+                    _ => {
+                        fields.insert(key.into_owned(), value.to_json());
+                    }
+                }
+            }
+            fields
+        });
+        if !fields.is_empty() {
+            insert_path(record_map, &self.namespace, Value::Object(fields));
+        }
+
+        serde_json::to_writer(&mut out, &record)?;
+        out.write_all(b"\n")
+    }
+}
@@ -0,0 +1,232 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::fmt::format::{DefaultFields, Writer};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+use tracing_subscriber::Layer;
+
+use super::{Format, FormatContext};
+use crate::compat_layer::{FieldOptions, Visitor};
+
+/// Uses a [`Format`] as the event formatter of `tracing_subscriber::fmt::Layer`.
+///
+/// `fmt::Layer` doesn't record span fields into the `Visitor` extension that formatters read
+/// them from, so a [`FieldRecorder`](crate::recorder::FieldRecorder) has to be installed too,
+/// without one events are written without the fields of their spans. Made with
+/// [`FieldRecorder::event_format`](crate::recorder::FieldRecorder::event_format), the recorder's
+/// redaction, field order and numbers are applied to the fields of events too.
+///
+/// ```
+/// use layer::fmt::json::JsonFormatter;
+/// use layer::recorder::FieldRecorder;
+/// use layer::redaction::{Action, Redaction};
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let redaction = Redaction::new().name("password", Action::Drop);
+/// let recorder = FieldRecorder::new().with_redaction(redaction);
+/// let event_format = recorder.event_format(JsonFormatter::new());
+/// let subscriber = tracing_subscriber::registry()
+///     .with(recorder)
+///     .with(tracing_subscriber::fmt::layer().event_format(event_format));
+/// # drop(subscriber);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AsFormatEvent<F> {
+    format: F,
+    options: Arc<FieldOptions>,
+}
+
+impl<F> AsFormatEvent<F> {
+    /// Formats the fields of events as they are, in alphabetical order.
+    pub fn new(format: F) -> Self {
+        Self::with_options(format, Arc::default())
+    }
+
+    pub(crate) fn with_options(format: F, options: Arc<FieldOptions>) -> Self {
+        Self { format, options }
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for AsFormatEvent<F>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+    F: Format<S>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        #[allow(clippy::let_unit_value)]
+        let () = TextOnly::<S, F>::ASSERT;
+
+        let ctx = WithOptions {
+            ctx,
+            options: &self.options,
+            ansi: writer.has_ansi_escapes(),
+        };
+        self.format.format_event(event, &ctx, &mut writer)
+    }
+}
+
+// Fails to compile for a binary `Format`, whose `format_event` can't write anything, rather than
+// dropping every event at runtime.
+struct TextOnly<S, F>(PhantomData<(S, F)>);
+
+impl<S, F> TextOnly<S, F>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    F: Format<S>,
+{
+    const ASSERT: () = assert!(
+        F::TEXT,
+        "binary formats can only be used with `CompatLayer`"
+    );
+}
+
+/// A `FmtContext` that records events with the options of a `FieldRecorder`.
+struct WithOptions<'a, S, N>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    ctx: &'a FmtContext<'a, S, N>,
+    options: &'a FieldOptions,
+    // Whether the `fmt::Layer` writes ANSI escape codes, see its `with_ansi`.
+    ansi: bool,
+}
+
+impl<S, N> FormatContext<S> for WithOptions<'_, S, N>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn span(&self, id: &Id) -> Option<SpanRef<'_, S>> {
+        self.ctx.span(id)
+    }
+
+    fn lookup_current(&self) -> Option<SpanRef<'_, S>> {
+        self.ctx.lookup_current()
+    }
+
+    fn record_event(&self, event: &Event<'_>) -> Visitor<'static> {
+        let mut visitor = self.options.visitor();
+        event.record(&mut visitor);
+        self.options.apply(event.fields(), visitor.fields_mut());
+        visitor
+    }
+
+    fn ansi(&self) -> Option<bool> {
+        Some(self.ansi)
+    }
+}
+
+/// Uses an existing `FormatEvent` as a [`Format`], e.g. to emit the `tracing_subscriber::fmt`
+/// formats through `CompatLayer`.
+///
+/// A `FormatEvent` can only be driven by `tracing_subscriber::fmt::Layer`, so this wraps one and
+/// captures what it writes. It can only be used from a layer, formatting fails when the
+/// [`FormatContext`] doesn't provide a layer `Context`.
+pub struct FromFormatEvent<S, N, E> {
+    inner: tracing_subscriber::fmt::Layer<S, N, E, CaptureWriter>,
+}
+
+impl<S, E> FromFormatEvent<S, DefaultFields, E>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    E: FormatEvent<S, DefaultFields> + 'static,
+{
+    pub fn new(event_format: E) -> Self {
+        Self {
+            inner: tracing_subscriber::fmt::layer()
+                .event_format(event_format)
+                .with_writer(CaptureWriter)
+                .with_ansi(false),
+        }
+    }
+}
+
+impl<S, N, E> FromFormatEvent<S, N, E>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+    E: FormatEvent<S, N> + 'static,
+{
+    /// Sets the formatter used for the fields of events and spans.
+    pub fn fmt_fields<N2>(self, fmt_fields: N2) -> FromFormatEvent<S, N2, E>
+    where
+        N2: for<'writer> FormatFields<'writer> + 'static,
+        E: FormatEvent<S, N2>,
+    {
+        FromFormatEvent {
+            inner: self.inner.fmt_fields(fmt_fields),
+        }
+    }
+}
+
+impl<S, N, E> Format<S> for FromFormatEvent<S, N, E>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+    E: FormatEvent<S, N> + 'static,
+{
+    fn format_event<W: fmt::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut writer: W,
+    ) -> fmt::Result {
+        let ctx = ctx.layer_context().ok_or(fmt::Error)?;
+
+        // Events emitted while this one is being formatted are captured after it, so only take
+        // what was written from here on.
+        let start = CAPTURED.with(|captured| captured.borrow().len());
+        self.inner.on_event(event, ctx);
+        let output = CAPTURED.with(|captured| captured.borrow_mut().split_off(start));
+
+        writer.write_str(&String::from_utf8_lossy(&output))
+    }
+
+    // `fmt::Layer` keeps the formatted fields of each span in its extensions.
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.inner.on_record(id, values, ctx);
+    }
+}
+
+thread_local! {
+    static CAPTURED: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+struct CaptureWriter;
+
+impl io::Write for CaptureWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        CAPTURED.with(|captured| captured.borrow_mut().extend_from_slice(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CaptureWriter {
+    type Writer = CaptureWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        CaptureWriter
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::marker;
+use std::time::UNIX_EPOCH;
+
+use serde::ser::{SerializeMap, Serializer as _};
+use serde_json::ser::Serializer;
+use tracing_core::{Event, Level, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::fmt::WriteAdaptor;
+
+use super::time::{Clock, SystemClock};
+use super::{merge_fields, with_span_fields, CollisionPolicy, Format, FormatContext};
+
+// The additional fields set by the formatter, span and event fields with the same name are sent
+// with a `fields.` prefix instead.
+const RESERVED_FIELDS: &[&str] = &[
+    "_span",
+    "_file",
+    "_line",
+    "_target",
+    "_trace_id",
+    "_span_id",
+];
+
+/// Formats events as GELF 1.1 messages for Graylog, see `layer::gelf::GelfWriter` to send them.
+///
+/// The first line of the message is the `short_message`, messages with more than one line are
+/// also sent whole as the `full_message`. GELF requires a `short_message`, so if the first line is
+/// blank the name of the span is sent instead, or the name of the event outside of a span. Span and event fields are sent as additional fields,
+/// prefixed with `_`, characters that GELF doesn't allow in names are replaced with `_` and `id`
+/// is sent as `__id` because `_id` is reserved. GELF values are strings or numbers, so booleans,
+/// lists and objects are sent as their JSON text and nulls are left out. Fields that would clash
+/// with the `_span`, `_file`, `_line` and `_target` fields set by the formatter get a `fields.`
+/// prefix, e.g. `_fields.span`, and fields whose names only differ once they're sanitized get a
+/// suffix, e.g. `a b` and `a/b` are sent as `_a_b` and `_a_b_2`.
+pub struct GelfFormatter<S> {
+    host: String,
+    clock: Box<dyn Clock>,
+    collisions: CollisionPolicy,
+    _registry: marker::PhantomData<S>,
+}
+
+impl<S> Default for GelfFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> GelfFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    pub fn new() -> Self {
+        Self {
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            clock: Box::new(SystemClock),
+            collisions: CollisionPolicy::default(),
+            _registry: marker::PhantomData,
+        }
+    }
+
+    /// Overrides the `host`, which is the hostname of the system by default.
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    /// Sets the clock used to timestamp events, the system clock by default.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets how fields that appear on several spans, or on a span and the event, are resolved so
+    /// that keys aren't duplicated. Fields are flat so `Nest` is treated like `Prefix`.
+    pub fn with_collisions(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = match policy {
+            CollisionPolicy::Nest => CollisionPolicy::Prefix,
+            policy => policy,
+        };
+        self
+    }
+}
+
+// Syslog severities, GELF has nothing below debug.
+fn syslog_level(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+fn additional_field(key: &str) -> String {
+    let mut name = String::with_capacity(key.len() + 1);
+    name.push('_');
+    if key == "id" {
+        name.push('_');
+    }
+    name.extend(key.chars().map(|c| match c {
+        c if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' => c,
+        _ => '_',
+    }));
+    name
+}
+
+// Gives a field a `fields.` prefix if the formatter sets a field with its name, and a suffix if
+// another field already has its name.
+fn unique_field(names: &mut HashSet<String>, name: String) -> String {
+    let name = match RESERVED_FIELDS.contains(&name.as_str()) {
+        true => format!("_fields.{}", &name[1..]),
+        false => name,
+    };
+    let mut unique = name.clone();
+    let mut n = 1;
+    while names.contains(&unique) {
+        n += 1;
+        unique = format!("{}_{}", name, n);
+    }
+    names.insert(unique.clone());
+    unique
+}
+
+impl<S> Format<S> for GelfFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn format_event<W: fmt::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut writer: W,
+    ) -> fmt::Result {
+        self.write_event(event, ctx, WriteAdaptor(&mut writer))
+            .map_err(|_| fmt::Error)
+    }
+
+    fn format_event_bytes(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        self.write_event(event, ctx, buf)
+    }
+}
+
+impl<S> GelfFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn write_event<O: io::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut out: O,
+    ) -> io::Result<()> {
+        let mut visit = || {
+            let mut serializer = Serializer::new(&mut out);
+            let mut serializer = serializer.serialize_map(None)?;
+            let mut visitor = ctx.record_event(event);
+            let metadata = event.metadata();
+
+            let current_span = ctx.event_span(event);
+
+            let message = visitor.fields_mut().remove("message");
+            let message = message
+                .as_ref()
+                .and_then(|m| m.as_str())
+                .unwrap_or(metadata.name());
+            let first_line = message.lines().next().unwrap_or_default();
+            let short_message = match first_line.trim().is_empty() {
+                true => current_span
+                    .as_ref()
+                    .map_or(metadata.name(), |span| span.metadata().name()),
+                false => first_line,
+            };
+            let short_message = match short_message.is_empty() {
+                true => "-",
+                false => short_message,
+            };
+            let timestamp = self
+                .clock
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+
+            serializer.serialize_entry("version", "1.1")?;
+            serializer.serialize_entry("host", &self.host)?;
+            serializer.serialize_entry("short_message", short_message)?;
+            if first_line.len() != message.len() {
+                serializer.serialize_entry("full_message", message)?;
+            }
+            // Seconds with millisecond precision.
+            serializer.serialize_entry("timestamp", &(timestamp.as_millis() as f64 / 1000.0))?;
+            serializer.serialize_entry("level", &syslog_level(metadata.level()))?;
+
+            if let Some(span) = &current_span {
+                serializer.serialize_entry("_span", span.metadata().name())?;
+            }
+            if let Some(file) = metadata.file() {
+                serializer.serialize_entry("_file", file)?;
+            }
+            if let Some(line) = metadata.line() {
+                serializer.serialize_entry("_line", &line)?;
+            }
+            serializer.serialize_entry("_target", metadata.target())?;
+
+            #[cfg(feature = "opentelemetry")]
+            if let Some(ids) = current_span.as_ref().and_then(crate::otel::otel_ids) {
+                serializer.serialize_entry("_trace_id", &ids.trace_id)?;
+                serializer.serialize_entry("_span_id", &ids.span_id)?;
+            }
+
+            with_span_fields(current_span, |spans| {
+                let mut names = HashSet::new();
+                for (key, value) in merge_fields(self.collisions, spans, &visitor) {
+                    if value.is_null() {
+                        continue;
+                    }
+                    let key = unique_field(&mut names, additional_field(&key));
+                    match value.as_str().is_some() || value.is_number() {
+                        true => serializer.serialize_entry(&key, value)?,
+                        false => serializer.serialize_entry(&key, &value.to_string())?,
+                    }
+                }
+                Ok(())
+            })?;
+
+            serializer.end()
+        };
+
+        visit()?;
+        out.write_all(b"\n")
+    }
+}
@@ -1,24 +1,46 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt;
+use std::io;
 use std::marker;
+use std::sync::Arc;
 
 use serde::ser::{SerializeMap, Serializer as _};
+use serde::Serialize;
 use serde_json::ser::Serializer;
 use tracing_core::{Event, Subscriber};
-use tracing_subscriber::layer::Context;
-use tracing_subscriber::registry::LookupSpan;
-use tracing_subscriber::registry::SpanRef;
+use tracing_subscriber::registry::{Extensions, LookupSpan, SpanRef};
 
 use crate::compat_layer::Visitor;
-use crate::fmt::WriteAdaptor;
+use crate::fields::{FieldMap, FieldValue};
+use crate::span_ids::SpanIds;
 
-use super::Format;
+use super::time::{Clock, SystemClock, TimestampFormat};
+use super::{
+    merge_fields, resolve_field, with_span_fields, with_span_scope, CollisionPolicy, Format,
+    FormatContext, ScopeVec, SpanFields, WriteAdaptor,
+};
 
 pub struct JsonFormatter<S> {
     // Store as string to avoid reformatting each time it's needed.
     pid: String,
+    clock: Box<dyn Clock>,
+    timestamp: Option<TimestampFormat>,
+    collisions: CollisionPolicy,
+    pinned: Arc<[String]>,
+    span_ids: bool,
     _registry: marker::PhantomData<S>,
 }
 
+impl<S> Default for JsonFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<S> JsonFormatter<S>
 where
     S: Subscriber + for<'lookup> LookupSpan<'lookup>,
@@ -26,26 +48,168 @@ where
     pub fn new() -> Self {
         Self {
             pid: std::process::id().to_string(),
+            clock: Box::new(SystemClock),
+            timestamp: Some(TimestampFormat::default()),
+            collisions: CollisionPolicy::default(),
+            pinned: Arc::new([]),
+            span_ids: false,
             _registry: marker::PhantomData,
         }
     }
 
-    fn spans<M>(serializer: &mut M, span: SpanRef<'_, S>) -> Result<(), M::Error>
+    /// Sets the format of the `timestamp` field, RFC 3339 in UTC by default.
+    pub fn with_timestamp(mut self, format: TimestampFormat) -> Self {
+        self.timestamp = Some(format);
+        self
+    }
+
+    /// Omits the `timestamp` field entirely.
+    pub fn without_timestamp(mut self) -> Self {
+        self.timestamp = None;
+        self
+    }
+
+    /// Sets the clock used to timestamp events, the system clock by default.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets how fields that appear on several spans, or on a span and the event, are resolved so
+    /// that keys aren't duplicated. Fields named like the keys the formatter writes, e.g. `level`,
+    /// are written as `fields.level`.
+    pub fn with_collisions(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = policy;
+        self
+    }
+
+    /// Writes these fields right after `title`, in this order, wherever they were recorded. Keys
+    /// are matched against the names that fields are written with, e.g. `get_cat.id` with
+    /// `CollisionPolicy::Prefix`, and with `CollisionPolicy::Nest` only the event's fields can be
+    /// pinned. Fields named like the keys the formatter writes, e.g. `level`, can't be pinned.
+    pub fn with_pinned_keys<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.pinned = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Writes `span_id`, `parent_span_id` and `root_span_id` for the span of each event, see
+    /// [`SpanIds`]. The OpenTelemetry span id is then written as `otel.span_id`.
+    pub fn with_span_ids(mut self, span_ids: bool) -> Self {
+        self.span_ids = span_ids;
+        self
+    }
+
+    // Fields that would collide with the header are written in the usual place, with a prefix.
+    fn is_pinned(&self, key: &str) -> bool {
+        !self.is_reserved(key) && self.pinned.iter().any(|pinned| pinned == key)
+    }
+
+    // Whether the formatter writes an entry with this key itself, fields with these names are
+    // written with a `fields.` prefix instead.
+    fn is_reserved(&self, key: &str) -> bool {
+        match key {
+            "timestamp" => self.timestamp.is_some(),
+            "level" | "title" | "span" | "source.filename" | "source.line" | "source.target"
+            | "source.pid" => true,
+            "span_id" => self.span_ids || cfg!(feature = "opentelemetry"),
+            "parent_span_id" | "root_span_id" => self.span_ids,
+            "trace_id" => cfg!(feature = "opentelemetry"),
+            "otel.span_id" => self.span_ids && cfg!(feature = "opentelemetry"),
+            "spans" => self.collisions == CollisionPolicy::Nest,
+            _ => false,
+        }
+    }
+
+    // The key a field is written with.
+    fn output_key<'k>(&self, key: &'k str) -> Cow<'k, str> {
+        if self.is_reserved(key) {
+            Cow::Owned(format!("fields.{}", key))
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+
+    fn pinned_values(
+        &self,
+        spans: &SpanFields<'_>,
+        event: &Visitor<'_>,
+    ) -> Vec<(&str, FieldValue)> {
+        self.pinned
+            .iter()
+            .filter(|key| self.is_pinned(key))
+            .filter_map(|key| {
+                let value = resolve_field(self.collisions, spans, event, key)?;
+                Some((key.as_str(), value.clone()))
+            })
+            .collect()
+    }
+
+    fn fields<M>(
+        &self,
+        serializer: &mut M,
+        spans: &SpanFields<'_>,
+        event: &Visitor<'_>,
+    ) -> Result<(), M::Error>
     where
         M: SerializeMap,
     {
-        for span in span.scope().from_root() {
-            let extensions = span.extensions();
-            let visitor = extensions
-                .get::<Visitor>()
-                .expect("Extensions should contain visitor, this is a bug");
-
-            for (key, val) in visitor.fields() {
-                serializer.serialize_entry(key, val)?;
+        if self.collisions == CollisionPolicy::Nest {
+            serializer.serialize_entry("spans", &NestedSpans(spans))?;
+            for (key, val) in event.fields() {
+                if !self.is_pinned(key) {
+                    serializer.serialize_entry(&self.output_key(key), val)?;
+                }
+            }
+            return Ok(());
+        }
+
+        for (key, val) in merge_fields(self.collisions, spans, event) {
+            if !self.is_pinned(&key) {
+                serializer.serialize_entry(&self.output_key(&key), val)?;
             }
         }
         Ok(())
     }
+
+    // Looks up what the header needs from the span's extensions, this has to happen before they're
+    // locked to read the fields of the span's scope.
+    fn span_header(&self, span: &SpanRef<'_, S>) -> SpanHeader {
+        SpanHeader {
+            name: span.metadata().name(),
+            ids: self.span_ids.then(|| SpanIds::of(span)),
+            #[cfg(feature = "opentelemetry")]
+            otel: crate::otel::otel_ids(span),
+        }
+    }
+}
+
+pub(super) struct NestedSpans<'a, 'b>(pub(super) &'b SpanFields<'a>);
+
+impl Serialize for NestedSpans<'_, '_> {
+    fn serialize<M: serde::Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        use serde::ser::SerializeSeq;
+
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for (name, visitor) in self.0 {
+            seq.serialize_element(&NestedSpan(name, visitor.fields()))?;
+        }
+        seq.end()
+    }
+}
+
+struct NestedSpan<'a>(&'a str, &'a FieldMap<'static>);
+
+impl Serialize for NestedSpan<'_> {
+    fn serialize<M: serde::Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("name", self.0)?;
+        map.serialize_entry("fields", self.1)?;
+        map.end()
+    }
 }
 
 impl<S> Format<S> for JsonFormatter<S>
@@ -55,56 +219,296 @@ where
     fn format_event<W: fmt::Write>(
         &self,
         event: &Event<'_>,
-        ctx: Context<'_, S>,
+        ctx: &dyn FormatContext<S>,
         mut writer: W,
-    ) -> Result<(), fmt::Error>
-    where
-        W: fmt::Write,
-    {
-        let mut visit = || {
-            let mut serializer = Serializer::new(WriteAdaptor(&mut writer));
-            let mut serializer = serializer.serialize_map(None)?;
-            let mut visitor = Visitor::default();
-            event.record(&mut visitor);
-            let metadata = event.metadata();
-
-            let current_span = event
-                .parent()
-                .and_then(|id| ctx.span(id))
-                .or_else(|| ctx.lookup_current());
-
-            serializer.serialize_entry("level", metadata.level().as_str())?;
-            let message = visitor.fields_mut().remove("message");
-
-            serializer.serialize_entry(
-                "title",
-                message
-                    .as_ref()
-                    .and_then(|m| m.as_str())
-                    .unwrap_or(metadata.name()),
-            )?;
+    ) -> fmt::Result {
+        self.write_event(event, ctx, &mut WriteAdaptor(&mut writer))
+            .map_err(|_| fmt::Error)
+    }
+
+    fn format_event_bytes(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        self.write_event(event, ctx, buf)
+    }
+}
+
+// What the header of an event says about its span.
+struct SpanHeader {
+    name: &'static str,
+    ids: Option<SpanIds>,
+    #[cfg(feature = "opentelemetry")]
+    otel: Option<crate::otel::OtelIds>,
+}
+
+/// The fields of a span and its ancestors rendered as the entries of a JSON object, e.g.
+/// `,"correlation_id":"abc","attempt":1`, which is kept in the span's extensions so that they
+/// aren't serialized again for every event in the span.
+#[derive(Clone)]
+struct SpanFragment {
+    config: FragmentConfig,
+    // The version of each span's `Visitor`, from the root, when this was rendered. Recording
+    // fields on any of them, e.g. in `on_record`, changes the version and makes this stale.
+    versions: ScopeVec<u64>,
+    json: Vec<u8>,
+    keys: HashSet<String>,
+}
 
-            if let Some(span) = &current_span {
-                serializer.serialize_entry("span", span.metadata().name())?;
+/// The configuration of the formatter that rendered a `SpanFragment`. Several formatters can write
+/// events for the same spans, a fragment is only reused by a formatter configured the same way.
+#[derive(Clone, PartialEq)]
+struct FragmentConfig {
+    collisions: CollisionPolicy,
+    // Pinned fields are written before the fragment, so they're left out of it.
+    pinned: Arc<[String]>,
+    // Which keys are reserved and written with a prefix, see `is_reserved`. The ones that depend
+    // on the `opentelemetry` feature are the same for every formatter.
+    timestamp: bool,
+    span_ids: bool,
+}
+
+impl<S> JsonFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn fragment_config(&self) -> FragmentConfig {
+        FragmentConfig {
+            collisions: self.collisions,
+            pinned: self.pinned.clone(),
+            timestamp: self.timestamp.is_some(),
+            span_ids: self.span_ids,
+        }
+    }
+
+    fn write_event<W: io::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        buf: &mut W,
+    ) -> io::Result<()> {
+        let mut visitor = ctx.record_event(event);
+        let current_span = ctx.event_span(event);
+        let header = current_span.as_ref().map(|span| self.span_header(span));
+        let id = current_span.as_ref().map(|span| span.id());
+
+        let rendered = with_span_scope(current_span, |spans, extensions| {
+            let pinned = self.pinned_values(spans, &visitor);
+            let mut serializer = Serializer::new(&mut *buf);
+            let mut map = serializer.serialize_map(None)?;
+            self.serialize_header(&mut map, event, &mut visitor, header.as_ref(), &pinned)?;
+            // The object is left open, without `end`, to write the fields by hand so that the
+            // span fields can be spliced in.
+            let rendered = match extensions {
+                Some(extensions) => self.write_fields(buf, spans, extensions, &visitor)?,
+                None => {
+                    self.write_entries(buf, &visitor)?;
+                    None
+                }
+            };
+            buf.write_all(b"}\n")?;
+            Ok::<_, io::Error>(rendered)
+        })?;
+
+        if let (Some(fragment), Some(span)) = (rendered, id.and_then(|id| ctx.span(&id))) {
+            span.extensions_mut().replace(fragment);
+        }
+        Ok(())
+    }
+
+    // Writes the fields of the event and its spans, returns the span fragment if it had to be
+    // rendered again so that it can be kept once the extensions are unlocked.
+    fn write_fields<W: io::Write>(
+        &self,
+        buf: &mut W,
+        spans: &SpanFields<'_>,
+        extensions: &Extensions<'_>,
+        event: &Visitor<'_>,
+    ) -> io::Result<Option<SpanFragment>> {
+        let versions: ScopeVec<_> = spans.iter().map(|(_, visitor)| visitor.version()).collect();
+        let config = self.fragment_config();
+        let fragment = match extensions
+            .get::<SpanFragment>()
+            .filter(|f| f.config == config && f.versions == versions)
+        {
+            Some(fragment) => Cow::Borrowed(fragment),
+            None => Cow::Owned(self.render(spans, config, versions)?),
+        };
+
+        // Splicing is only possible if none of the span fields need to be resolved against the
+        // event's.
+        if event
+            .fields()
+            .keys()
+            .any(|key| fragment.keys.contains(key.as_ref()))
+        {
+            self.write_merged(buf, spans, event)?;
+        } else {
+            buf.write_all(&fragment.json)?;
+            self.write_entries(buf, event)?;
+        }
+
+        Ok(match fragment {
+            Cow::Owned(fragment) => Some(fragment),
+            Cow::Borrowed(_) => None,
+        })
+    }
+
+    fn render(
+        &self,
+        spans: &SpanFields<'_>,
+        config: FragmentConfig,
+        versions: ScopeVec<u64>,
+    ) -> io::Result<SpanFragment> {
+        let mut json = Vec::new();
+        let keys = if self.collisions == CollisionPolicy::Nest {
+            write_entry(&mut json, "spans", &NestedSpans(spans))?;
+            HashSet::from(["spans".to_string()])
+        } else {
+            let no_event = Visitor::default();
+            let entries = merge_fields(self.collisions, spans, &no_event);
+            for (key, value) in &entries {
+                if !self.is_pinned(key) {
+                    write_entry(&mut json, &self.output_key(key), value)?;
+                }
             }
+            entries
+                .into_iter()
+                .map(|(key, _)| key.into_owned())
+                .collect()
+        };
+
+        Ok(SpanFragment {
+            config,
+            versions,
+            json,
+            keys,
+        })
+    }
+
+    fn write_merged<W: io::Write>(
+        &self,
+        buf: &mut W,
+        spans: &SpanFields<'_>,
+        event: &Visitor<'_>,
+    ) -> io::Result<()> {
+        if self.collisions == CollisionPolicy::Nest {
+            write_entry(buf, "spans", &NestedSpans(spans))?;
+            return self.write_entries(buf, event);
+        }
 
-            serializer.serialize_entry("source.filename", &event.metadata().file())?;
-            serializer.serialize_entry("source.line", &event.metadata().line())?;
-            serializer.serialize_entry("source.target", &event.metadata().target())?;
-            serializer.serialize_entry("source.pid", &self.pid)?;
+        for (key, value) in merge_fields(self.collisions, spans, event) {
+            if !self.is_pinned(&key) {
+                write_entry(buf, &self.output_key(&key), value)?;
+            }
+        }
+        Ok(())
+    }
 
-            if let Some(current_span) = current_span {
-                Self::spans(&mut serializer, current_span)?;
+    fn write_entries<W: io::Write>(&self, buf: &mut W, visitor: &Visitor<'_>) -> io::Result<()> {
+        for (key, value) in visitor.fields() {
+            if !self.is_pinned(key) {
+                write_entry(buf, &self.output_key(key), value)?;
             }
+        }
+        Ok(())
+    }
+
+    /// Serializes the record of an event as a map, this is shared with `BinaryFormatter` so that
+    /// both write the same fields.
+    pub(super) fn serialize_record<M: serde::Serializer>(
+        &self,
+        serializer: M,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+    ) -> Result<M::Ok, M::Error> {
+        let mut serializer = serializer.serialize_map(None)?;
+        let mut visitor = ctx.record_event(event);
+        let current_span = ctx.event_span(event);
+        let header = current_span.as_ref().map(|span| self.span_header(span));
+
+        with_span_fields(current_span, |spans| {
+            let pinned = self.pinned_values(spans, &visitor);
+            self.serialize_header(
+                &mut serializer,
+                event,
+                &mut visitor,
+                header.as_ref(),
+                &pinned,
+            )?;
+            self.fields(&mut serializer, spans, &visitor)
+        })?;
+
+        serializer.end()
+    }
 
-            for (k, v) in visitor.fields() {
-                serializer.serialize_entry(k, v)?;
+    // Everything but the fields of the event and its spans.
+    fn serialize_header<M: SerializeMap>(
+        &self,
+        serializer: &mut M,
+        event: &Event<'_>,
+        visitor: &mut Visitor<'_>,
+        span: Option<&SpanHeader>,
+        pinned: &[(&str, FieldValue)],
+    ) -> Result<(), M::Error> {
+        let metadata = event.metadata();
+
+        if let Some(format) = &self.timestamp {
+            serializer.serialize_entry("timestamp", &format.format(self.clock.now()))?;
+        }
+
+        serializer.serialize_entry("level", metadata.level().as_str())?;
+        let message = visitor.fields_mut().remove("message");
+
+        serializer.serialize_entry(
+            "title",
+            message
+                .as_ref()
+                .and_then(|m| m.as_str())
+                .unwrap_or(metadata.name()),
+        )?;
+        for (key, value) in pinned {
+            serializer.serialize_entry(key, value)?;
+        }
+
+        if let Some(span) = span {
+            serializer.serialize_entry("span", span.name)?;
+            if let Some(ids) = &span.ids {
+                serializer.serialize_entry("span_id", &ids.span_id)?;
+                if let Some(parent_span_id) = &ids.parent_span_id {
+                    serializer.serialize_entry("parent_span_id", parent_span_id)?;
+                }
+                serializer.serialize_entry("root_span_id", &ids.root_span_id)?;
             }
+        }
 
-            serializer.end()
-        };
+        serializer.serialize_entry("source.filename", &event.metadata().file())?;
+        serializer.serialize_entry("source.line", &event.metadata().line())?;
+        serializer.serialize_entry("source.target", &event.metadata().target())?;
+        serializer.serialize_entry("source.pid", &self.pid)?;
+
+        #[cfg(feature = "opentelemetry")]
+        if let Some(ids) = span.and_then(|span| span.otel.as_ref()) {
+            serializer.serialize_entry("trace_id", &ids.trace_id)?;
+            let key = if self.span_ids {
+                "otel.span_id"
+            } else {
+                "span_id"
+            };
+            serializer.serialize_entry(key, &ids.span_id)?;
+        }
 
-        visit().map_err(|_| fmt::Error)?;
-        writeln!(writer)
+        Ok(())
     }
 }
+
+// Writes an entry of an object that already has at least one.
+fn write_entry<W: io::Write>(buf: &mut W, key: &str, value: &impl Serialize) -> io::Result<()> {
+    buf.write_all(b",")?;
+    serde_json::to_writer(&mut *buf, key)?;
+    buf.write_all(b":")?;
+    serde_json::to_writer(&mut *buf, value)?;
+    Ok(())
+}
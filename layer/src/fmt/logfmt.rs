@@ -0,0 +1,232 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::marker;
+
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::fields::FieldValue;
+
+use super::time::{Clock, SystemClock, TimestampFormat};
+use super::{merge_fields, with_span_fields, CollisionPolicy, Format, FormatContext};
+
+/// Formats events as logfmt, one line of `key=value` pairs per event, with the same fields as
+/// `JsonFormatter`.
+///
+/// Values are quoted when they are empty or contain whitespace, `=`, `"` or control characters,
+/// inside quotes `"`, `\` and control characters are escaped as they would be in JSON. Objects
+/// and arrays are written as JSON.
+pub struct LogfmtFormatter<S> {
+    // Store as string to avoid reformatting each time it's needed.
+    pid: String,
+    clock: Box<dyn Clock>,
+    timestamp: Option<TimestampFormat>,
+    collisions: CollisionPolicy,
+    _registry: marker::PhantomData<S>,
+}
+
+impl<S> Default for LogfmtFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> LogfmtFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    pub fn new() -> Self {
+        Self {
+            pid: std::process::id().to_string(),
+            clock: Box::new(SystemClock),
+            timestamp: Some(TimestampFormat::default()),
+            collisions: CollisionPolicy::default(),
+            _registry: marker::PhantomData,
+        }
+    }
+
+    /// Sets the format of the `timestamp` field, RFC 3339 in UTC by default.
+    pub fn with_timestamp(mut self, format: TimestampFormat) -> Self {
+        self.timestamp = Some(format);
+        self
+    }
+
+    /// Omits the `timestamp` field entirely.
+    pub fn without_timestamp(mut self) -> Self {
+        self.timestamp = None;
+        self
+    }
+
+    /// Sets the clock used to timestamp events, the system clock by default.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets how fields that appear on several spans, or on a span and the event, are resolved so
+    /// that keys aren't duplicated. Lines are flat so `Nest` is treated like `Prefix`. Fields named
+    /// like the keys the formatter writes, e.g. `level`, are written as `fields.level`.
+    pub fn with_collisions(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = match policy {
+            CollisionPolicy::Nest => CollisionPolicy::Prefix,
+            policy => policy,
+        };
+        self
+    }
+
+    // Whether the formatter writes an entry with this key itself.
+    fn is_reserved(&self, key: &str) -> bool {
+        match key {
+            "timestamp" => self.timestamp.is_some(),
+            "level" | "title" | "span" | "source.filename" | "source.line" | "source.target"
+            | "source.pid" => true,
+            "trace_id" | "span_id" => cfg!(feature = "opentelemetry"),
+            _ => false,
+        }
+    }
+
+    // The key a field is written with.
+    fn output_key<'k>(&self, key: Cow<'k, str>) -> Cow<'k, str> {
+        if self.is_reserved(&key) {
+            Cow::Owned(format!("fields.{}", key))
+        } else {
+            key
+        }
+    }
+}
+
+impl<S> Format<S> for LogfmtFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn format_event<W: fmt::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        writer: W,
+    ) -> fmt::Result {
+        let mut line = Line::new(writer);
+        let mut visitor = ctx.record_event(event);
+        let metadata = event.metadata();
+
+        let current_span = ctx.event_span(event);
+
+        if let Some(format) = &self.timestamp {
+            line.entry("timestamp", &format.format(self.clock.now()).to_string())?;
+        }
+
+        line.entry("level", metadata.level().as_str())?;
+        let message = visitor.fields_mut().remove("message");
+        line.entry(
+            "title",
+            message
+                .as_ref()
+                .and_then(|m| m.as_str())
+                .unwrap_or(metadata.name()),
+        )?;
+
+        if let Some(span) = &current_span {
+            line.entry("span", span.metadata().name())?;
+        }
+
+        if let Some(file) = metadata.file() {
+            line.entry("source.filename", file)?;
+        }
+        if let Some(number) = metadata.line() {
+            line.entry("source.line", &number.to_string())?;
+        }
+        line.entry("source.target", metadata.target())?;
+        line.entry("source.pid", &self.pid)?;
+
+        #[cfg(feature = "opentelemetry")]
+        if let Some(ids) = current_span.as_ref().and_then(crate::otel::otel_ids) {
+            line.entry("trace_id", &ids.trace_id)?;
+            line.entry("span_id", &ids.span_id)?;
+        }
+
+        with_span_fields(current_span, |spans| {
+            merge_fields(self.collisions, spans, &visitor)
+                .into_iter()
+                .try_for_each(|(key, value)| line.value_entry(&self.output_key(key), value))
+        })?;
+
+        line.finish()
+    }
+}
+
+// Writes the `key=value` pairs of a single line.
+struct Line<W> {
+    writer: W,
+    empty: bool,
+}
+
+impl<W: fmt::Write> Line<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            empty: true,
+        }
+    }
+
+    fn entry(&mut self, key: &str, value: &str) -> fmt::Result {
+        if !self.empty {
+            self.writer.write_char(' ')?;
+        }
+        self.empty = false;
+
+        write_key(&mut self.writer, key)?;
+        self.writer.write_char('=')?;
+        write_value(&mut self.writer, value)
+    }
+
+    fn value_entry(&mut self, key: &str, value: &FieldValue) -> fmt::Result {
+        match value.as_str() {
+            Some(s) => self.entry(key, s),
+            None => self.entry(key, &value.to_string()),
+        }
+    }
+
+    fn finish(mut self) -> fmt::Result {
+        writeln!(self.writer)
+    }
+}
+
+/// Writes a key, keys can't be quoted so anything that would break the line is replaced.
+pub(super) fn write_key<W: fmt::Write>(writer: &mut W, key: &str) -> fmt::Result {
+    for c in key.chars() {
+        writer.write_char(if needs_quotes(c) { '_' } else { c })?;
+    }
+    Ok(())
+}
+
+/// Writes a value, quoting it if needed.
+pub(super) fn write_value<W: fmt::Write>(writer: &mut W, value: &str) -> fmt::Result {
+    if value.is_empty() || value.chars().any(needs_quotes) {
+        write_quoted(writer, value)
+    } else {
+        writer.write_str(value)
+    }
+}
+
+fn needs_quotes(c: char) -> bool {
+    c == '=' || c == '"' || c.is_whitespace() || c.is_control() || c == char::REPLACEMENT_CHARACTER
+}
+
+fn write_quoted<W: fmt::Write>(writer: &mut W, value: &str) -> fmt::Result {
+    writer.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => writer.write_str("\\\"")?,
+            '\\' => writer.write_str("\\\\")?,
+            '\n' => writer.write_str("\\n")?,
+            '\r' => writer.write_str("\\r")?,
+            '\t' => writer.write_str("\\t")?,
+            c if c.is_control() => write!(writer, "\\u{:04x}", c as u32)?,
+            c => writer.write_char(c)?,
+        }
+    }
+    writer.write_char('"')
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::io;
+use std::marker;
+use std::time::UNIX_EPOCH;
+
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing_core::{Event, Level, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::fmt::WriteAdaptor;
+
+use super::time::{Clock, SystemClock};
+use super::{merge_fields, with_span_fields, CollisionPolicy, Format, FormatContext};
+
+/// Formats each event as an OTLP/JSON `ExportLogsServiceRequest` holding a single `LogRecord`, so
+/// that files of them can be read by the `otlpjsonfile` receiver of the OpenTelemetry collector
+/// without any parsing configuration. The `filelog` receiver can't read them without a parsing
+/// pipeline, the record is nested in the arrays of the request.
+///
+/// Span and event fields are the attributes of the record, along with `code.filepath` and
+/// `code.lineno`, and the target of the event is the instrumentation scope. The resource has
+/// `service.name`, `host.name` and `process.pid` plus any attributes added with
+/// `with_resource_attribute`.
+pub struct OtlpFormatter<S> {
+    resource: Value,
+    clock: Box<dyn Clock>,
+    collisions: CollisionPolicy,
+    _registry: marker::PhantomData<S>,
+}
+
+impl<S> OtlpFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    pub fn new(service_name: impl Into<String>) -> Self {
+        let host = gethostname::gethostname().to_string_lossy().into_owned();
+        let resource = json!({
+            "attributes": [
+                attribute("service.name", &Value::String(service_name.into())),
+                attribute("host.name", &Value::String(host)),
+                attribute("process.pid", &std::process::id().into()),
+            ]
+        });
+
+        Self {
+            resource,
+            clock: Box::new(SystemClock),
+            collisions: CollisionPolicy::default(),
+            _registry: marker::PhantomData,
+        }
+    }
+
+    /// Adds an attribute to the resource, e.g. `deployment.environment`. An attribute with the same
+    /// key replaces the existing one.
+    pub fn with_resource_attribute(mut self, key: &str, value: impl Into<Value>) -> Self {
+        if let Some(attributes) = self.resource["attributes"].as_array_mut() {
+            attributes.retain(|attribute| attribute["key"] != key);
+            attributes.push(attribute(key, &value.into()));
+        }
+        self
+    }
+
+    /// Sets the clock used to timestamp events, the system clock by default.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets how fields that appear on several spans, or on a span and the event, are resolved so
+    /// that keys aren't duplicated. Attributes are flat so `Nest` is treated like `Prefix`.
+    pub fn with_collisions(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = match policy {
+            CollisionPolicy::Nest => CollisionPolicy::Prefix,
+            policy => policy,
+        };
+        self
+    }
+}
+
+fn severity_number(level: &Level) -> u8 {
+    match *level {
+        Level::TRACE => 1,
+        Level::DEBUG => 5,
+        Level::INFO => 9,
+        Level::WARN => 13,
+        Level::ERROR => 17,
+    }
+}
+
+/// Converts a field to an OTLP `AnyValue`. 64 bit integers are strings in the JSON encoding of
+/// protobuf, unsigned integers too big for an `intValue` are sent as strings.
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({}),
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => json!({ "intValue": i.to_string() }),
+            (None, Some(_)) if n.is_f64() => json!({ "doubleValue": n }),
+            _ => json!({ "stringValue": n.to_string() }),
+        },
+        Value::String(s) => json!({ "stringValue": s }),
+        Value::Array(values) => json!({
+            "arrayValue": { "values": values.iter().map(any_value).collect::<Vec<_>>() }
+        }),
+        Value::Object(map) => json!({
+            "kvlistValue": {
+                "values": map.iter().map(|(k, v)| attribute(k, v)).collect::<Vec<_>>()
+            }
+        }),
+    }
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    json!({ "key": key, "value": any_value(value) })
+}
+
+impl<S> Format<S> for OtlpFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn format_event<W: fmt::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut writer: W,
+    ) -> fmt::Result {
+        self.write_event(event, ctx, WriteAdaptor(&mut writer))
+            .map_err(|_| fmt::Error)
+    }
+
+    fn format_event_bytes(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        self.write_event(event, ctx, buf)
+    }
+}
+
+// An export request with a single log record. The resource is only borrowed as it's the same for
+// every record.
+struct ExportRequest<'a> {
+    resource: &'a Value,
+    scope: &'a str,
+    record: LogRecord<'a>,
+}
+
+impl Serialize for ExportRequest<'_> {
+    fn serialize<M: Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("resourceLogs", &[ResourceLogs(self)])?;
+        map.end()
+    }
+}
+
+struct ResourceLogs<'a>(&'a ExportRequest<'a>);
+
+impl Serialize for ResourceLogs<'_> {
+    fn serialize<M: Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("resource", self.0.resource)?;
+        map.serialize_entry("scopeLogs", &[ScopeLogs(self.0)])?;
+        map.end()
+    }
+}
+
+struct ScopeLogs<'a>(&'a ExportRequest<'a>);
+
+impl Serialize for ScopeLogs<'_> {
+    fn serialize<M: Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("scope", &json!({ "name": self.0.scope }))?;
+        map.serialize_entry("logRecords", &[&self.0.record])?;
+        map.end()
+    }
+}
+
+struct LogRecord<'a> {
+    time: String,
+    level: &'a Level,
+    body: &'a str,
+    attributes: Vec<Value>,
+    #[cfg(feature = "opentelemetry")]
+    ids: Option<crate::otel::OtelIds>,
+}
+
+impl Serialize for LogRecord<'_> {
+    fn serialize<M: Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("timeUnixNano", &self.time)?;
+        map.serialize_entry("observedTimeUnixNano", &self.time)?;
+        map.serialize_entry("severityNumber", &severity_number(self.level))?;
+        map.serialize_entry("severityText", self.level.as_str())?;
+        map.serialize_entry("body", &json!({ "stringValue": self.body }))?;
+        map.serialize_entry("attributes", &self.attributes)?;
+
+        #[cfg(feature = "opentelemetry")]
+        if let Some(ids) = &self.ids {
+            map.serialize_entry("traceId", &ids.trace_id)?;
+            map.serialize_entry("spanId", &ids.span_id)?;
+        }
+
+        map.end()
+    }
+}
+
+impl<S> OtlpFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn write_event<O: io::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut out: O,
+    ) -> io::Result<()> {
+        let mut visitor = ctx.record_event(event);
+        let metadata = event.metadata();
+
+        let current_span = ctx.event_span(event);
+
+        let message = visitor.fields_mut().remove("message");
+        let message = message
+            .as_ref()
+            .and_then(|m| m.as_str())
+            .unwrap_or(metadata.name());
+        let time = self
+            .clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string();
+
+        let mut attributes = Vec::new();
+        if let Some(file) = metadata.file() {
+            attributes.push(attribute("code.filepath", &file.into()));
+        }
+        if let Some(line) = metadata.line() {
+            attributes.push(attribute("code.lineno", &line.into()));
+        }
+        #[cfg(feature = "opentelemetry")]
+        let ids = current_span.as_ref().and_then(crate::otel::otel_ids);
+
+        with_span_fields(current_span, |spans| {
+            for (key, value) in merge_fields(self.collisions, spans, &visitor) {
+                if !value.is_null() {
+                    attributes.push(attribute(&key, &value.to_json()));
+                }
+            }
+        });
+
+        let request = ExportRequest {
+            resource: &self.resource,
+            scope: metadata.target(),
+            record: LogRecord {
+                time,
+                level: metadata.level(),
+                body: message,
+                attributes,
+                #[cfg(feature = "opentelemetry")]
+                ids,
+            },
+        };
+
+        serde_json::to_writer(&mut out, &request)?;
+        out.write_all(b"\n")
+    }
+}
@@ -0,0 +1,238 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::IsTerminal;
+use std::marker;
+
+use tracing_core::{Event, Level, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::fields::FieldValue;
+use crate::span_events::SpanEvents;
+
+use super::logfmt::{write_key, write_value};
+use super::time::{Clock, SystemClock, TimestampFormat};
+use super::{ansi_for, merge_fields, with_span_fields, CollisionPolicy, Format, FormatContext};
+
+// The fields written by `CompatLayer` on span end events.
+const TIMING_FIELDS: &[&str] = &[
+    "elapsed",
+    "busy",
+    "idle",
+    "elapsed_ns",
+    "busy_ns",
+    "idle_ns",
+    "elapsed_ms",
+    "busy_ms",
+    "idle_ms",
+];
+
+/// Formats events for people reading them in a terminal during development, e.g.
+///
+/// ```text
+/// 2023-06-01T12:30:00.000000Z  INFO get_cat > get_cat_link: Fetching link! correlation_id=abc
+/// ```
+///
+/// The timings of span end events are written right after the message, and stand out when colors
+/// are on. Span fields named like them are written with a `fields.` prefix, e.g. `fields.elapsed`.
+///
+/// By default the layer decides whether the output is colored, see `CompatLayer::with_ansi` and the
+/// `with_ansi` of `tracing_subscriber::fmt`'s layer. Either is overridden with `with_ansi` or
+/// `with_ansi_for`.
+pub struct PrettyFormatter<S> {
+    clock: Box<dyn Clock>,
+    timestamp: Option<TimestampFormat>,
+    collisions: CollisionPolicy,
+    // `None` to color the output when the layer says so.
+    ansi: Option<bool>,
+    _registry: marker::PhantomData<S>,
+}
+
+impl<S> Default for PrettyFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> PrettyFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    /// Creates a formatter that colors the output when the layer says so.
+    ///
+    /// ```
+    /// use layer::compat_layer::CompatLayer;
+    /// use layer::fmt::pretty::PrettyFormatter;
+    ///
+    /// // Colors when stdout is a terminal, unless the layer is told otherwise with `with_ansi`.
+    /// let layer = CompatLayer::new(PrettyFormatter::new(), std::io::stdout);
+    /// # let _: CompatLayer<tracing_subscriber::Registry, _, _> = layer;
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            clock: Box::new(SystemClock),
+            timestamp: Some(TimestampFormat::default()),
+            collisions: CollisionPolicy::default(),
+            ansi: None,
+            _registry: marker::PhantomData,
+        }
+    }
+
+    /// Sets the format of the timestamp, RFC 3339 in UTC by default.
+    pub fn with_timestamp(mut self, format: TimestampFormat) -> Self {
+        self.timestamp = Some(format);
+        self
+    }
+
+    /// Omits the timestamp entirely.
+    pub fn without_timestamp(mut self) -> Self {
+        self.timestamp = None;
+        self
+    }
+
+    /// Sets the clock used to timestamp events, the system clock by default.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets how fields that appear on several spans, or on a span and the event, are resolved so
+    /// that keys aren't duplicated. Lines are flat so `Nest` is treated like `Prefix`.
+    pub fn with_collisions(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = match policy {
+            CollisionPolicy::Nest => CollisionPolicy::Prefix,
+            policy => policy,
+        };
+        self
+    }
+
+    /// Turns coloring the output with ANSI escape codes on or off, whatever the writer is.
+    pub fn with_ansi(mut self, ansi: bool) -> Self {
+        self.ansi = Some(ansi);
+        self
+    }
+
+    /// Colors the output if `stream`, e.g. `std::io::stdout()`, is a terminal and the `NO_COLOR`
+    /// environment variable isn't set.
+    pub fn with_ansi_for(self, stream: &impl IsTerminal) -> Self {
+        self.with_ansi(ansi_for(stream))
+    }
+}
+
+fn paint<W: fmt::Write>(ansi: bool, writer: &mut W, style: Style, text: &str) -> fmt::Result {
+    match ansi {
+        true => write!(writer, "\x1b[{}m{}\x1b[0m", style.0, text),
+        false => writer.write_str(text),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Style(&'static str);
+
+impl Style {
+    const DIMMED: Style = Style("2");
+    const BOLD: Style = Style("1");
+    const ITALIC: Style = Style("3");
+    const TIMING: Style = Style("1;36");
+
+    fn level(level: &Level) -> Style {
+        match *level {
+            Level::TRACE => Style("35"),
+            Level::DEBUG => Style("34"),
+            Level::INFO => Style("32"),
+            Level::WARN => Style("33"),
+            Level::ERROR => Style("31"),
+        }
+    }
+}
+
+impl<S> Format<S> for PrettyFormatter<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn format_event<W: fmt::Write>(
+        &self,
+        event: &Event<'_>,
+        ctx: &dyn FormatContext<S>,
+        mut writer: W,
+    ) -> fmt::Result {
+        let mut visitor = ctx.record_event(event);
+        let metadata = event.metadata();
+
+        let current_span = ctx.event_span(event);
+        let ansi = self.ansi.or_else(|| ctx.ansi()).unwrap_or(false);
+
+        if let Some(format) = &self.timestamp {
+            let timestamp = format.format(self.clock.now()).to_string();
+            paint(ansi, &mut writer, Style::DIMMED, &timestamp)?;
+            writer.write_char(' ')?;
+        }
+
+        let level = format!("{:>5}", metadata.level().as_str());
+        paint(ansi, &mut writer, Style::level(metadata.level()), &level)?;
+        writer.write_char(' ')?;
+
+        if let Some(span) = &current_span {
+            let path: Vec<_> = span
+                .scope()
+                .from_root()
+                .map(|span| span.metadata().name())
+                .collect();
+            paint(ansi, &mut writer, Style::BOLD, &path.join(" > "))?;
+            writer.write_str(": ")?;
+        }
+
+        let message = visitor.fields_mut().remove("message");
+        writer.write_str(
+            message
+                .as_ref()
+                .and_then(|m| m.as_str())
+                .unwrap_or(metadata.name()),
+        )?;
+
+        // Only span end events have timings, other fields with these names are just fields.
+        let mut timings = Vec::new();
+        if ctx.span_event() == Some(SpanEvents::CLOSE) {
+            for name in TIMING_FIELDS {
+                if let Some(value) = visitor.fields_mut().remove(name) {
+                    writer.write_char(' ')?;
+                    paint(
+                        ansi,
+                        &mut writer,
+                        Style::TIMING,
+                        &format!("{}={}", name, text(&value)),
+                    )?;
+                    timings.push(*name);
+                }
+            }
+        }
+
+        with_span_fields(current_span, |spans| {
+            for (key, value) in merge_fields(self.collisions, spans, &visitor) {
+                let key = match timings.contains(&key.as_ref()) {
+                    true => Cow::Owned(format!("fields.{}", key)),
+                    false => key,
+                };
+                writer.write_char(' ')?;
+
+                let mut pair = String::new();
+                write_key(&mut pair, &key)?;
+                pair.push('=');
+                paint(ansi, &mut writer, Style::ITALIC, &pair)?;
+                write_value(&mut writer, &text(value))?;
+            }
+            Ok(())
+        })?;
+
+        writeln!(writer)
+    }
+}
+
+fn text(value: &FieldValue) -> String {
+    match value.as_str() {
+        Some(s) => s.to_string(),
+        None => value.to_string(),
+    }
+}
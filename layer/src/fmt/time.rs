@@ -0,0 +1,96 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde::{Serialize, Serializer};
+
+/// A source of wall-clock time for formatters.
+///
+/// Formatters ask a `Clock` for the current time instead of calling `SystemTime::now` directly so
+/// that tests can pin the time and assert on exact output.
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> SystemTime;
+}
+
+/// The default clock, reads the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that is stuck at a single point in time.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+/// How the timestamp of an event is rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// RFC 3339 in UTC with microsecond precision, e.g. `2023-06-01T12:30:00.000000Z`.
+    #[default]
+    Rfc3339Utc,
+    /// RFC 3339 with the local UTC offset, e.g. `2023-06-01T13:30:00.000000+01:00`.
+    Rfc3339Local,
+    /// Milliseconds since the unix epoch, as a number.
+    EpochMillis,
+    /// Nanoseconds since the unix epoch, as a number.
+    EpochNanos,
+}
+
+impl TimestampFormat {
+    pub fn format(&self, time: SystemTime) -> Timestamp {
+        match self {
+            Self::Rfc3339Utc => Timestamp::Text(
+                DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Micros, true),
+            ),
+            Self::Rfc3339Local => Timestamp::Text(
+                DateTime::<Local>::from(time).to_rfc3339_opts(SecondsFormat::Micros, false),
+            ),
+            Self::EpochMillis => Timestamp::Number(saturate(since_epoch(time).as_millis())),
+            Self::EpochNanos => Timestamp::Number(saturate(since_epoch(time).as_nanos())),
+        }
+    }
+}
+
+/// A formatted timestamp, either a string or a number depending on the [`TimestampFormat`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Timestamp {
+    Text(String),
+    Number(u64),
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Text(text) => serializer.serialize_str(text),
+            Self::Number(number) => serializer.serialize_u64(*number),
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text(text) => f.write_str(text),
+            Self::Number(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+// Times before the epoch are clamped to the epoch rather than being rendered as negative numbers.
+fn since_epoch(time: SystemTime) -> std::time::Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+fn saturate(value: u128) -> u64 {
+    u64::try_from(value).unwrap_or(u64::MAX)
+}
@@ -0,0 +1,219 @@
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The chunk size recommended by Graylog for messages sent across networks, it fits in the MTU
+/// of most links.
+pub const WAN_CHUNK_SIZE: usize = 1420;
+/// The chunk size recommended by Graylog for messages sent within a local network.
+pub const LAN_CHUNK_SIZE: usize = 8154;
+
+// GELF rejects messages split into more chunks than this.
+const MAX_CHUNKS: usize = 128;
+// Magic bytes, 8 byte message id, sequence number and sequence count.
+const CHUNK_HEADER_LEN: usize = 12;
+
+/// Sends GELF messages, e.g. from `GelfFormatter`, to Graylog over UDP or TCP.
+///
+/// Each call to `write` is sent as a single message, which is how `CompatLayer` writes events, so
+/// this can also be wrapped in a `NonBlocking` writer. A trailing newline is stripped. Errors
+/// from the network are returned from `write` and ignored by `CompatLayer`.
+#[derive(Clone)]
+pub struct GelfWriter {
+    transport: Arc<Transport>,
+}
+
+enum Transport {
+    Udp {
+        socket: UdpSocket,
+        chunk_size: usize,
+        compress: bool,
+        message_ids: AtomicU64,
+    },
+    Tcp {
+        addr: Vec<SocketAddr>,
+        // Connected lazily and again after an error.
+        stream: Mutex<Option<TcpStream>>,
+    },
+}
+
+/// Configures a `GelfWriter`, see `GelfWriter::builder`.
+#[derive(Clone, Debug)]
+pub struct GelfWriterBuilder {
+    chunk_size: usize,
+    compress: bool,
+}
+
+impl Default for GelfWriterBuilder {
+    fn default() -> Self {
+        Self {
+            chunk_size: WAN_CHUNK_SIZE,
+            compress: false,
+        }
+    }
+}
+
+impl GelfWriterBuilder {
+    /// Sets the maximum size of UDP datagrams, e.g. `LAN_CHUNK_SIZE`, it has no effect on TCP.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(CHUNK_HEADER_LEN + 1);
+        self
+    }
+
+    /// Compresses UDP messages with zlib, it has no effect on TCP.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Sends messages as UDP datagrams, messages bigger than the chunk size are split into GELF
+    /// chunks.
+    pub fn udp(self, addr: impl ToSocketAddrs) -> io::Result<GelfWriter> {
+        let addr: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        let local: SocketAddr = match addr.first() {
+            Some(SocketAddr::V6(_)) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+            _ => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(&addr[..])?;
+
+        // Message ids only need to be unique for a few seconds on each host, start from the time
+        // so that restarts don't reuse them.
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        Ok(GelfWriter::new(Transport::Udp {
+            socket,
+            chunk_size: self.chunk_size,
+            compress: self.compress,
+            message_ids: AtomicU64::new(start),
+        }))
+    }
+
+    /// Sends messages over a TCP connection, each followed by a null byte. GELF over TCP doesn't
+    /// support compression or chunking.
+    pub fn tcp(self, addr: impl ToSocketAddrs) -> io::Result<GelfWriter> {
+        let addr: Vec<_> = addr.to_socket_addrs()?.collect();
+        let stream = TcpStream::connect(&addr[..])?;
+
+        Ok(GelfWriter::new(Transport::Tcp {
+            addr,
+            stream: Mutex::new(Some(stream)),
+        }))
+    }
+}
+
+impl GelfWriter {
+    /// Sends messages over UDP with the default configuration, chunks of `WAN_CHUNK_SIZE` and no
+    /// compression.
+    pub fn udp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        GelfWriterBuilder::default().udp(addr)
+    }
+
+    /// Sends messages over TCP, each followed by a null byte.
+    pub fn tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        GelfWriterBuilder::default().tcp(addr)
+    }
+
+    pub fn builder() -> GelfWriterBuilder {
+        GelfWriterBuilder::default()
+    }
+
+    fn new(transport: Transport) -> Self {
+        Self {
+            transport: Arc::new(transport),
+        }
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        match &*self.transport {
+            Transport::Udp {
+                socket,
+                chunk_size,
+                compress,
+                message_ids,
+            } => {
+                let compressed;
+                let message = match compress {
+                    true => {
+                        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                        encoder.write_all(message)?;
+                        compressed = encoder.finish()?;
+                        &compressed[..]
+                    }
+                    false => message,
+                };
+
+                if message.len() <= *chunk_size {
+                    socket.send(message)?;
+                    return Ok(());
+                }
+
+                let chunks = message.chunks(*chunk_size - CHUNK_HEADER_LEN);
+                let count = chunks.len();
+                if count > MAX_CHUNKS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("GELF message needs {} chunks, the limit is 128", count),
+                    ));
+                }
+
+                let id = message_ids.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+                let mut datagram = Vec::with_capacity(*chunk_size);
+                for (i, chunk) in chunks.enumerate() {
+                    datagram.clear();
+                    datagram.extend_from_slice(&[0x1e, 0x0f]);
+                    datagram.extend_from_slice(&id);
+                    datagram.extend_from_slice(&[i as u8, count as u8]);
+                    datagram.extend_from_slice(chunk);
+                    socket.send(&datagram)?;
+                }
+                Ok(())
+            }
+            Transport::Tcp { addr, stream } => {
+                let mut stream = stream.lock().unwrap_or_else(|e| e.into_inner());
+                let connection = match &mut *stream {
+                    Some(connection) => connection,
+                    None => stream.insert(TcpStream::connect(&addr[..])?),
+                };
+
+                let result = connection
+                    .write_all(message)
+                    .and_then(|_| connection.write_all(&[0]));
+                if result.is_err() {
+                    // Reconnect on the next message.
+                    *stream = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+impl io::Write for GelfWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let message = buf.strip_suffix(b"\n").unwrap_or(buf);
+        self.send(message)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for GelfWriter {
+    type Writer = GelfWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
@@ -1,3 +1,14 @@
 pub mod compat_layer;
 pub mod compat_span_ext;
+pub mod error;
+pub mod fields;
 pub mod fmt;
+pub mod gelf;
+pub mod non_blocking;
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
+pub mod recorder;
+pub mod redaction;
+pub mod slow_spans;
+pub mod span_events;
+pub mod span_ids;
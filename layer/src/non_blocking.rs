@@ -0,0 +1,339 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread::{self, JoinHandle, ThreadId};
+use std::time::{Duration, Instant};
+
+use tracing_core::dispatcher::{self, WeakDispatch};
+use tracing_core::Dispatch;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// What to do with a line when the queue to the writer thread is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for the writer thread to make room, this applies backpressure to the caller.
+    #[default]
+    Block,
+    /// Discard the line that is being written.
+    DropNewest,
+    /// Discard the oldest line in the queue to make room for the one being written.
+    DropOldest,
+}
+
+/// A `MakeWriter` that hands finished lines to a dedicated writer thread so that a slow sink
+/// (e.g. a stdout pipe nobody is reading) doesn't stall the threads that are logging.
+///
+/// Each call to `write` is treated as a single line, which is how `CompatLayer` writes events.
+#[derive(Clone)]
+pub struct NonBlocking {
+    shared: Arc<Shared>,
+}
+
+/// Flushes and stops the writer thread when dropped, it should be held for as long as the
+/// `NonBlocking` writer is in use, usually until the end of `main`.
+#[must_use = "dropping the guard stops the writer thread"]
+pub struct WorkerGuard {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NonBlockingBuilder {
+    capacity: usize,
+    overflow: OverflowPolicy,
+    report_interval: Option<Duration>,
+    thread_name: String,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    dropped: AtomicU64,
+    unreported: AtomicU64,
+    report_interval: Option<Duration>,
+    // The subscriber dropped lines are reported to, the global default if there is none.
+    report_to: OnceLock<WeakDispatch>,
+    // The writer thread, which must never wait for room in its own queue.
+    worker: OnceLock<ThreadId>,
+}
+
+struct State {
+    lines: VecDeque<Vec<u8>>,
+    shutdown: bool,
+}
+
+impl Default for NonBlockingBuilder {
+    fn default() -> Self {
+        Self {
+            capacity: 128_000,
+            overflow: OverflowPolicy::default(),
+            report_interval: Some(Duration::from_secs(10)),
+            thread_name: String::from("layer-writer"),
+        }
+    }
+}
+
+impl NonBlockingBuilder {
+    /// Sets the maximum number of lines waiting to be written.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Sets how often the number of dropped lines is reported, `None` disables the report. The
+    /// writer thread checks the count after each batch of lines and when nothing has been written
+    /// for that long, the last of it is reported when the `WorkerGuard` is dropped.
+    ///
+    /// The report is a WARN event with the target `layer::non_blocking` and the count in
+    /// `dropped_lines`, so it's formatted like any other event. See [`NonBlocking::report_to`] for
+    /// the subscriber it goes to.
+    pub fn with_report_interval(mut self, interval: Option<Duration>) -> Self {
+        self.report_interval = interval;
+        self
+    }
+
+    pub fn with_thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = name.into();
+        self
+    }
+
+    pub fn finish<T>(self, writer: T) -> (NonBlocking, WorkerGuard)
+    where
+        T: io::Write + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                lines: VecDeque::new(),
+                shutdown: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: self.capacity,
+            overflow: self.overflow,
+            dropped: AtomicU64::new(0),
+            unreported: AtomicU64::new(0),
+            report_interval: self.report_interval,
+            report_to: OnceLock::new(),
+            worker: OnceLock::new(),
+        });
+
+        let handle = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(self.thread_name)
+                .spawn(move || shared.work(writer))
+                .expect("failed to spawn the writer thread")
+        };
+
+        let guard = WorkerGuard {
+            shared: shared.clone(),
+            handle: Some(handle),
+        };
+
+        (NonBlocking { shared }, guard)
+    }
+}
+
+impl NonBlocking {
+    /// Creates a writer with the default configuration: a queue of 128,000 lines that blocks when
+    /// full.
+    pub fn new<T>(writer: T) -> (NonBlocking, WorkerGuard)
+    where
+        T: io::Write + Send + 'static,
+    {
+        NonBlockingBuilder::default().finish(writer)
+    }
+
+    pub fn builder() -> NonBlockingBuilder {
+        NonBlockingBuilder::default()
+    }
+
+    /// The total number of lines that have been dropped because the queue was full.
+    pub fn dropped_lines(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends the reports of dropped lines to this subscriber rather than the global default.
+    /// `CompatLayer` does this for the `NonBlocking` writer it's given, only the first call has an
+    /// effect.
+    pub fn report_to(&self, dispatch: &Dispatch) {
+        let _ = self.shared.report_to.set(dispatch.downgrade());
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panic while holding the lock can't leave the queue in an inconsistent state.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Reports the lines dropped since the last report, unless there are none or the last report
+    // was less than the report interval ago. `force` ignores the interval.
+    fn report(&self, last_report: &mut Instant, force: bool) {
+        let Some(interval) = self.report_interval else {
+            return;
+        };
+
+        if !force && last_report.elapsed() < interval {
+            return;
+        }
+
+        let dropped = self.unreported.swap(0, Ordering::Relaxed);
+        if dropped == 0 {
+            return;
+        }
+        *last_report = Instant::now();
+
+        let report = || {
+            tracing::warn!(
+                target: "layer::non_blocking",
+                dropped_lines = dropped,
+                "Dropped log lines because the writer could not keep up"
+            )
+        };
+        match self.report_to.get().and_then(WeakDispatch::upgrade) {
+            Some(dispatch) => dispatcher::with_default(&dispatch, report),
+            None => report(),
+        }
+    }
+
+    fn drop_line(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.unreported.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn push(&self, line: Vec<u8>) {
+        let mut state = self.lock();
+
+        // The writer thread logs the reports, it mustn't wait on itself nor lose the last one.
+        if self.worker.get() == Some(&thread::current().id()) {
+            state.lines.push_back(line);
+            return;
+        }
+
+        if state.shutdown {
+            self.drop_line();
+            return;
+        }
+
+        if state.lines.len() >= self.capacity {
+            match self.overflow {
+                OverflowPolicy::Block => {
+                    while state.lines.len() >= self.capacity && !state.shutdown {
+                        state = self.not_full.wait(state).unwrap_or_else(|e| e.into_inner());
+                    }
+                    if state.shutdown {
+                        self.drop_line();
+                        return;
+                    }
+                }
+                OverflowPolicy::DropNewest => {
+                    self.drop_line();
+                    return;
+                }
+                OverflowPolicy::DropOldest => {
+                    state.lines.pop_front();
+                    self.drop_line();
+                }
+            }
+        }
+
+        state.lines.push_back(line);
+        drop(state);
+        self.not_empty.notify_one();
+    }
+
+    fn work<T: io::Write>(&self, mut writer: T) {
+        let _ = self.worker.set(thread::current().id());
+        let mut batch = Vec::new();
+        let mut last_report = Instant::now();
+
+        loop {
+            let shutdown = {
+                let mut state = self.lock();
+                while state.lines.is_empty() && !state.shutdown {
+                    let Some(interval) = self.report_interval else {
+                        state = self
+                            .not_empty
+                            .wait(state)
+                            .unwrap_or_else(|e| e.into_inner());
+                        continue;
+                    };
+
+                    let (next, timeout) = self
+                        .not_empty
+                        .wait_timeout(state, interval)
+                        .unwrap_or_else(|e| e.into_inner());
+                    state = next;
+                    // Nothing is being written, report what was dropped before it's forgotten.
+                    if timeout.timed_out() && state.lines.is_empty() {
+                        drop(state);
+                        self.report(&mut last_report, false);
+                        state = self.lock();
+                    }
+                }
+                batch.extend(state.lines.drain(..));
+                state.shutdown
+            };
+            self.not_full.notify_all();
+
+            for line in batch.drain(..) {
+                let _ = writer.write_all(&line);
+            }
+            let _ = writer.flush();
+
+            if shutdown {
+                // The last report is queued by this thread and written before it exits.
+                self.report(&mut last_report, true);
+                for line in self.lock().lines.drain(..) {
+                    let _ = writer.write_all(&line);
+                }
+                let _ = writer.flush();
+                return;
+            }
+            // The report is queued like any other line and written with the next batch.
+            self.report(&mut last_report, false);
+        }
+    }
+}
+
+impl io::Write for NonBlocking {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.shared.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for NonBlocking {
+    type Writer = NonBlocking;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        // The writer thread drains whatever is left in the queue before exiting.
+        self.shared.lock().shutdown = true;
+        self.shared.not_empty.notify_one();
+        self.shared.not_full.notify_all();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
+use tracing_core::Subscriber;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// The OpenTelemetry ids of a span, rendered as W3C trace context hex strings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OtelIds {
+    pub trace_id: String,
+    pub span_id: String,
+}
+
+/// Reads the ids that `tracing_opentelemetry::layer` keeps in the `OtelData` extension of the
+/// span, this only works when the `OpenTelemetryLayer` is installed on the same registry.
+///
+/// The span is only sent to the exporter once it closes, but its ids are allocated when it is
+/// created so they can be logged straight away.
+pub fn otel_ids<S>(span: &SpanRef<'_, S>) -> Option<OtelIds>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    let extensions = span.extensions();
+    let data = extensions.get::<OtelData>()?;
+
+    // Root spans are given a new trace id, other spans inherit the trace id of their parent.
+    let trace_id = data
+        .builder
+        .trace_id
+        .unwrap_or_else(|| data.parent_cx.span().span_context().trace_id());
+    let span_id = data.builder.span_id?;
+
+    if trace_id == TraceId::INVALID || span_id == SpanId::INVALID {
+        return None;
+    }
+
+    Some(OtelIds {
+        trace_id: format!("{:032x}", trace_id),
+        span_id: format!("{:016x}", span_id),
+    })
+}
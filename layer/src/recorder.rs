@@ -0,0 +1,116 @@
+use std::any::TypeId;
+use std::marker;
+use std::sync::Arc;
+
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::compat_layer::{record_new_span, record_values, FieldOptions, WithContext};
+use crate::fields::{FieldOrder, Numbers};
+use crate::fmt::event_format::AsFormatEvent;
+use crate::redaction::Redaction;
+
+/// A layer that only records span fields into the `Visitor` extension.
+///
+/// `CompatLayer` records span fields itself, this is needed when events are formatted by something
+/// else, e.g. a [`Format`](crate::fmt::Format) plugged into `tracing_subscriber::fmt::Layer` with
+/// [`AsFormatEvent`](crate::fmt::event_format::AsFormatEvent). It also makes
+/// [`CompatSpanExt`](crate::compat_span_ext::CompatSpanExt) work without a `CompatLayer`.
+///
+/// Span fields are recorded by whichever layer sees the span first, so when this is stacked with a
+/// `CompatLayer` give both the same redaction, field order and numbers.
+pub struct FieldRecorder<S> {
+    get_context: WithContext,
+    // Shared with the `AsFormatEvent`s made by `event_format`.
+    field_options: Arc<FieldOptions>,
+    _registry: marker::PhantomData<S>,
+}
+
+impl<S> FieldRecorder<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    pub fn new() -> Self {
+        Self {
+            get_context: WithContext::new::<S>(),
+            field_options: Arc::default(),
+            _registry: marker::PhantomData,
+        }
+    }
+
+    /// Redacts span fields before they're stored, as [`CompatLayer::with_redaction`] does. Events
+    /// formatted through the `AsFormatEvent`s made by [`event_format`](Self::event_format) are
+    /// redacted with the same rules.
+    ///
+    /// [`CompatLayer::with_redaction`]: crate::compat_layer::CompatLayer::with_redaction
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        Arc::make_mut(&mut self.field_options).redaction =
+            Some(redaction).filter(|r| !r.is_empty());
+        self
+    }
+
+    /// Sets the order span fields are stored in, alphabetical by default.
+    pub fn with_field_order(mut self, order: FieldOrder) -> Self {
+        Arc::make_mut(&mut self.field_options).order = order;
+        self
+    }
+
+    /// Sets how NaN and infinite floats, and integers too large for JavaScript, are stored.
+    pub fn with_numbers(mut self, numbers: Numbers) -> Self {
+        Arc::make_mut(&mut self.field_options).numbers = numbers;
+        self
+    }
+
+    /// Sets how large the JSON that `valuable` fields are stored as can get.
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    pub fn with_value_limits(mut self, limits: crate::fields::ValueLimits) -> Self {
+        Arc::make_mut(&mut self.field_options).value_limits = limits;
+        self
+    }
+
+    /// Uses a [`Format`](crate::fmt::Format) as the event formatter of
+    /// `tracing_subscriber::fmt::Layer`, recording the fields of events with the redaction, field
+    /// order and numbers of this recorder. Set these up before calling this.
+    pub fn event_format<F>(&self, format: F) -> AsFormatEvent<F> {
+        AsFormatEvent::with_options(format, self.field_options.clone())
+    }
+}
+
+impl<S> Default for FieldRecorder<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for FieldRecorder<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        record_new_span(attrs, id, &ctx, &self.field_options);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        record_values(id, values, &ctx, &self.field_options);
+    }
+
+    // SAFETY: See `CompatLayer::downcast_raw`.
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        match id {
+            id if id == TypeId::of::<Self>() => Some(self as *const _ as *const ()),
+            id if id == TypeId::of::<WithContext>() => {
+                Some(&self.get_context as *const _ as *const ())
+            }
+            id if id == TypeId::of::<FieldOptions>() => {
+                Some(&*self.field_options as *const FieldOptions as *const ())
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::fields::{FieldMap, FieldValue};
+
+/// What happens to a field, or part of a string value, matched by a redaction rule.
+#[derive(Clone)]
+pub enum Action {
+    /// Replace with the given text.
+    Replace(String),
+    /// Replace with the hex encoded HMAC-SHA256 of the value, so that the same value always
+    /// redacts to the same text and lines can still be joined on it without revealing it.
+    Hash(Hmac<Sha256>),
+    /// Remove the field entirely.
+    Drop,
+}
+
+impl Action {
+    /// Replace with `[REDACTED]`.
+    pub fn redact() -> Self {
+        Action::Replace(String::from("[REDACTED]"))
+    }
+
+    /// Hash with the given key, which should be kept secret and stable across restarts.
+    pub fn hash(key: &[u8]) -> Self {
+        Action::Hash(Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"))
+    }
+
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Action::Replace(replacement) => replacement.clone(),
+            Action::Hash(mac) => {
+                let mut mac = mac.clone();
+                mac.update(value.as_bytes());
+                mac.finalize()
+                    .into_bytes()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect()
+            }
+            Action::Drop => String::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Replace(replacement) => f.debug_tuple("Replace").field(replacement).finish(),
+            Action::Hash(_) => f.write_str("Hash"),
+            Action::Drop => f.write_str("Drop"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Name(String),
+    Glob(String),
+    Value(Regex),
+}
+
+/// Rules for scrubbing sensitive data out of fields before they are stored or logged.
+///
+/// Rules on field names apply to the whole value, and to the keys of objects nested in it, e.g.
+/// `password` in `{"user":{"password":"hunter2"}}`. Rules on values apply to the parts of string
+/// values (including Debug formatted values and the messages of errors) matched by the regex,
+/// except for [`Action::Drop`] which drops the whole field. Rules are applied in the order they
+/// were added.
+///
+/// ```
+/// use layer::redaction::{Action, Redaction};
+/// use regex::Regex;
+///
+/// let redaction = Redaction::new()
+///     .name("password", Action::Drop)
+///     .glob("*token*", Action::redact())
+///     .value(Regex::new(r"[\w.+-]+@[\w-]+\.[\w.]+").unwrap(), Action::hash(b"secret"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Redaction {
+    rules: Vec<(Matcher, Action)>,
+}
+
+impl Redaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches fields with exactly this name.
+    pub fn name(mut self, name: impl Into<String>, action: Action) -> Self {
+        self.rules.push((Matcher::Name(name.into()), action));
+        self
+    }
+
+    /// Matches field names against a pattern where `*` matches any run of characters and `?`
+    /// matches any single character.
+    pub fn glob(mut self, pattern: impl Into<String>, action: Action) -> Self {
+        self.rules.push((Matcher::Glob(pattern.into()), action));
+        self
+    }
+
+    /// Matches string values against a regex.
+    pub fn value(mut self, regex: Regex, action: Action) -> Self {
+        self.rules.push((Matcher::Value(regex), action));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn redact(&self, fields: &mut FieldMap<'_>) {
+        if self.is_empty() {
+            return;
+        }
+
+        fields.retain(|name, value| self.redact_field(name, value));
+    }
+
+    // Returns false if the field should be dropped.
+    fn redact_field(&self, name: &str, value: &mut FieldValue) -> bool {
+        for (matcher, action) in &self.rules {
+            match matcher {
+                Matcher::Name(n) if n == name => {}
+                Matcher::Glob(pattern) if glob_match(pattern, name) => {}
+                Matcher::Value(regex) => {
+                    if let FieldValue::Json(json) = value {
+                        let mut matched = false;
+                        for_each_string(json, &mut |s| matched |= regex.is_match(s));
+                        if !matched {
+                            continue;
+                        }
+                        if let Action::Drop = action {
+                            return false;
+                        }
+                        for_each_string(json, &mut |s| {
+                            let replaced =
+                                regex.replace_all(s, |c: &regex::Captures<'_>| action.apply(&c[0]));
+                            if let Cow::Owned(replaced) = replaced {
+                                *s = replaced;
+                            }
+                        });
+                        continue;
+                    }
+
+                    let Some(s) = value.as_str() else {
+                        continue;
+                    };
+                    if !regex.is_match(s) {
+                        continue;
+                    }
+                    if let Action::Drop = action {
+                        return false;
+                    }
+                    *value = regex
+                        .replace_all(s, |c: &regex::Captures<'_>| action.apply(&c[0]))
+                        .into_owned()
+                        .into();
+                    continue;
+                }
+                _ => continue,
+            }
+
+            if let Action::Drop = action {
+                return false;
+            }
+
+            let text = match value.as_str() {
+                Some(s) => action.apply(s),
+                None => action.apply(&value.to_string()),
+            };
+            *value = text.into();
+        }
+
+        if let FieldValue::Json(json) = value {
+            self.redact_nested(json);
+        }
+        true
+    }
+
+    // Applies the rules on names to the keys of nested objects.
+    fn redact_nested(&self, json: &mut Value) {
+        match json {
+            Value::Object(map) => map.retain(|key, value| self.redact_member(key, value)),
+            Value::Array(values) => values.iter_mut().for_each(|v| self.redact_nested(v)),
+            _ => {}
+        }
+    }
+
+    // Returns false if the member should be dropped.
+    fn redact_member(&self, key: &str, value: &mut Value) -> bool {
+        for (matcher, action) in &self.rules {
+            match matcher {
+                Matcher::Name(n) if n == key => {}
+                Matcher::Glob(pattern) if glob_match(pattern, key) => {}
+                _ => continue,
+            }
+
+            if let Action::Drop = action {
+                return false;
+            }
+
+            let text = match value.as_str() {
+                Some(s) => action.apply(s),
+                None => action.apply(&value.to_string()),
+            };
+            *value = Value::String(text);
+        }
+
+        self.redact_nested(value);
+        true
+    }
+}
+
+// Calls `f` with each string in a JSON value, e.g. the messages of a recorded error.
+fn for_each_string(value: &mut Value, f: &mut dyn FnMut(&mut String)) {
+    match value {
+        Value::String(s) => f(s),
+        Value::Array(values) => values.iter_mut().for_each(|v| for_each_string(v, f)),
+        Value::Object(map) => map.values_mut().for_each(|v| for_each_string(v, f)),
+        _ => {}
+    }
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Position to resume from in the pattern and text the last time a `*` was seen.
+    let mut star = None;
+    let (mut p, mut t) = (0, 0);
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
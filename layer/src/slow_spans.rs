@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing_core::dispatcher::WeakDispatch;
+use tracing_core::span::Id;
+use tracing_core::{Dispatch, Metadata};
+
+/// Latency budgets for spans, matched by the span's name or target.
+///
+/// Rules are checked in the order they were added and the first match wins, spans that no rule
+/// matches use the fallback if there is one.
+///
+/// ```
+/// use std::time::Duration;
+/// use layer::slow_spans::SpanThresholds;
+///
+/// let thresholds = SpanThresholds::new()
+///     .name("get_cat", Duration::from_secs(2))
+///     .target("demo::db", Duration::from_millis(100))
+///     .fallback(Duration::from_secs(5));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SpanThresholds {
+    rules: Vec<(Matcher, Duration)>,
+    fallback: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Name(String),
+    Target(String),
+}
+
+impl SpanThresholds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches spans with exactly this name.
+    pub fn name(mut self, name: impl Into<String>, threshold: Duration) -> Self {
+        self.rules.push((Matcher::Name(name.into()), threshold));
+        self
+    }
+
+    /// Matches spans with this target or a target inside it, e.g. `demo` matches spans from
+    /// `demo::cats`.
+    pub fn target(mut self, target: impl Into<String>, threshold: Duration) -> Self {
+        self.rules.push((Matcher::Target(target.into()), threshold));
+        self
+    }
+
+    /// Applies to spans that no other rule matched.
+    pub fn fallback(mut self, threshold: Duration) -> Self {
+        self.fallback = Some(threshold);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.fallback.is_none()
+    }
+
+    pub fn threshold(&self, meta: &Metadata<'_>) -> Option<Duration> {
+        self.rules
+            .iter()
+            .find(|(matcher, _)| match matcher {
+                Matcher::Name(name) => name == meta.name(),
+                Matcher::Target(target) => meta
+                    .target()
+                    .strip_prefix(target.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::")),
+            })
+            .map(|(_, threshold)| *threshold)
+            .or(self.fallback)
+    }
+}
+
+/// Called for each span that is still open past its deadline with the span's id, metadata, age
+/// and deadline.
+pub(crate) type OnHung =
+    dyn Fn(&Dispatch, &Id, &'static Metadata<'static>, Duration, Duration) + Send + Sync;
+
+/// Keeps track of open spans that have a deadline and reports the ones that are overdue from a
+/// thread of its own, so that spans that never close are still reported.
+pub(crate) struct Watchdog {
+    state: Mutex<State>,
+    changed: Condvar,
+    reported: Condvar,
+    started: AtomicBool,
+}
+
+#[derive(Default)]
+struct State {
+    spans: HashMap<Id, Watched>,
+    // The spans being reported right now, which can't close until they have been.
+    reporting: Vec<Id>,
+}
+
+struct Watched {
+    meta: &'static Metadata<'static>,
+    created: Instant,
+    deadline: Duration,
+    reported: bool,
+}
+
+// How long the watchdog waits at most before checking that the subscriber still exists.
+const MAX_WAIT: Duration = Duration::from_secs(1);
+
+impl Watchdog {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::default(),
+            changed: Condvar::new(),
+            reported: Condvar::new(),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn watch(&self, id: &Id, meta: &'static Metadata<'static>, deadline: Duration) {
+        self.lock().spans.insert(
+            id.clone(),
+            Watched {
+                meta,
+                created: Instant::now(),
+                deadline,
+                reported: false,
+            },
+        );
+        self.changed.notify_one();
+    }
+
+    /// Stops watching a span that is closing. If the span is being reported this waits until it
+    /// has been, as the registry could otherwise give its id to a new span in the meantime.
+    pub(crate) fn unwatch(&self, id: &Id) {
+        let mut state = self.lock();
+        while state.reporting.contains(id) {
+            state = self.reported.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.spans.remove(id);
+    }
+
+    /// Starts the watchdog thread for this dispatcher, only the first call does anything. The
+    /// thread stops once the dispatcher has been dropped.
+    pub(crate) fn start(self: &Arc<Self>, dispatch: &Dispatch, on_hung: Box<OnHung>) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let watchdog = self.clone();
+        let dispatch = dispatch.downgrade();
+        thread::Builder::new()
+            .name(String::from("layer-watchdog"))
+            .spawn(move || watchdog.run(dispatch, on_hung))
+            .expect("failed to spawn the watchdog thread");
+    }
+
+    fn run(&self, dispatch: WeakDispatch, on_hung: Box<OnHung>) {
+        let mut state = self.lock();
+
+        loop {
+            let Some(dispatch) = dispatch.upgrade() else {
+                return;
+            };
+
+            let now = Instant::now();
+            let mut wait = MAX_WAIT;
+            let mut hung = Vec::new();
+            for (id, watched) in state.spans.iter_mut().filter(|(_, w)| !w.reported) {
+                let age = now.duration_since(watched.created);
+                match watched.deadline.checked_sub(age) {
+                    Some(remaining) if !remaining.is_zero() => wait = wait.min(remaining),
+                    _ => {
+                        watched.reported = true;
+                        hung.push((id.clone(), watched.meta, age, watched.deadline));
+                    }
+                }
+            }
+
+            // The lock isn't held while reporting, as the layers the warnings are dispatched to
+            // may start or close spans of their own.
+            if !hung.is_empty() {
+                state.reporting = hung.iter().map(|(id, ..)| id.clone()).collect();
+                drop(state);
+                for (id, meta, age, deadline) in &hung {
+                    on_hung(&dispatch, id, meta, *age, *deadline);
+                }
+                state = self.lock();
+                state.reporting.clear();
+                self.reported.notify_all();
+                // Spans may have been added while the lock was released.
+                continue;
+            }
+
+            // Holding on to the dispatcher would keep the subscriber alive.
+            drop(dispatch);
+            state = self
+                .changed
+                .wait_timeout(state, wait)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
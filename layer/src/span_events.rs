@@ -0,0 +1,265 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::{BitOr, BitOrAssign};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use tracing_core::callsite::{self, Callsite, Identifier};
+use tracing_core::field::FieldSet;
+use tracing_core::metadata::Kind;
+use tracing_core::subscriber::Interest;
+use tracing_core::{Level, Metadata};
+
+/// Which points in the lifecycle of a span `CompatLayer` logs an event for.
+///
+/// Flags can be combined with `|`, e.g. `SpanEvents::NEW | SpanEvents::CLOSE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SpanEvents(u8);
+
+impl SpanEvents {
+    pub const NONE: SpanEvents = SpanEvents(0);
+    /// When the span is created.
+    ///
+    /// Layers added after `CompatLayer` haven't seen the span yet at this point, so anything they
+    /// keep in the span's extensions, e.g. OpenTelemetry ids, is missing from this event.
+    pub const NEW: SpanEvents = SpanEvents(1 << 0);
+    /// The first time the span is entered, this is the "start" event of `with_spans`.
+    pub const FIRST_ENTER: SpanEvents = SpanEvents(1 << 1);
+    /// Every time the span is entered, which can be many times for a span in an async context.
+    pub const ENTER: SpanEvents = SpanEvents(1 << 2);
+    /// Every time the span is exited.
+    pub const EXIT: SpanEvents = SpanEvents(1 << 3);
+    /// When the span closes, with its timings.
+    pub const CLOSE: SpanEvents = SpanEvents(1 << 4);
+    pub const ACTIVE: SpanEvents = SpanEvents(Self::ENTER.0 | Self::EXIT.0);
+    pub const FULL: SpanEvents = SpanEvents(Self::NEW.0 | Self::ACTIVE.0 | Self::CLOSE.0);
+
+    pub fn contains(self, other: SpanEvents) -> bool {
+        self.0 & other.0 == other.0 && other.0 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for SpanEvents {
+    type Output = SpanEvents;
+
+    fn bitor(self, rhs: SpanEvents) -> SpanEvents {
+        SpanEvents(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for SpanEvents {
+    fn bitor_assign(&mut self, rhs: SpanEvents) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// How the durations on the close event, `elapsed`, `busy` and `idle`, are written.
+///
+/// Flags can be combined with `|`, e.g. `DurationFields::TEXT | DurationFields::MILLIS` adds
+/// `elapsed_ms`, `busy_ms` and `idle_ms` next to the existing fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DurationFields(u8);
+
+impl DurationFields {
+    /// Human readable text from `format_duration`, e.g. `"12.30ms"`, in `elapsed`.
+    pub const TEXT: DurationFields = DurationFields(1 << 0);
+    /// Whole nanoseconds in `elapsed_ns`.
+    pub const NANOS: DurationFields = DurationFields(1 << 1);
+    /// Fractional milliseconds in `elapsed_ms`.
+    pub const MILLIS: DurationFields = DurationFields(1 << 2);
+
+    pub fn contains(self, other: DurationFields) -> bool {
+        self.0 & other.0 == other.0 && other.0 != 0
+    }
+}
+
+impl Default for DurationFields {
+    fn default() -> Self {
+        DurationFields::TEXT
+    }
+}
+
+impl BitOr for DurationFields {
+    type Output = DurationFields;
+
+    fn bitor(self, rhs: DurationFields) -> DurationFields {
+        DurationFields(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for DurationFields {
+    fn bitor_assign(&mut self, rhs: DurationFields) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// The `message` of each kind of span event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpanMessages {
+    pub new: Cow<'static, str>,
+    pub first_enter: Cow<'static, str>,
+    pub enter: Cow<'static, str>,
+    pub exit: Cow<'static, str>,
+    pub close: Cow<'static, str>,
+    /// A span that closed after its threshold, see `CompatLayer::with_slow_spans`.
+    pub slow: Cow<'static, str>,
+    /// A span that is still open past its deadline, see `CompatLayer::with_hung_spans`.
+    pub hung: Cow<'static, str>,
+}
+
+impl Default for SpanMessages {
+    fn default() -> Self {
+        Self {
+            new: Cow::Borrowed("new"),
+            first_enter: Cow::Borrowed("start"),
+            enter: Cow::Borrowed("enter"),
+            exit: Cow::Borrowed("exit"),
+            close: Cow::Borrowed("end"),
+            slow: Cow::Borrowed("slow span"),
+            hung: Cow::Borrowed("hung span"),
+        }
+    }
+}
+
+/// How long a span has existed and how much of that time it spent entered.
+///
+/// Kept in the span's extensions by `CompatLayer`, it's a type of our own to avoid interfering
+/// with other layers.
+pub(crate) struct Timings {
+    created: Instant,
+    entered: bool,
+    last_entered: Instant,
+    // A span can be entered again before it is exited, e.g. by recursion, it's only busy once.
+    depth: usize,
+    busy: Duration,
+}
+
+impl Timings {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            created: now,
+            entered: false,
+            last_entered: now,
+            depth: 0,
+            busy: Duration::ZERO,
+        }
+    }
+
+    /// Returns true the first time the span is entered.
+    pub(crate) fn enter(&mut self) -> bool {
+        let first = !self.entered;
+        if self.depth == 0 {
+            self.entered = true;
+            self.last_entered = Instant::now();
+        }
+        self.depth += 1;
+        first
+    }
+
+    pub(crate) fn exit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            self.busy += self.last_entered.elapsed();
+        }
+    }
+
+    /// The time since the span was created, and how much of it was spent inside and outside the
+    /// span, `(elapsed, busy, idle)`.
+    ///
+    /// All three are measured at the same instant, so `elapsed` is exactly `busy + idle`.
+    pub(crate) fn durations(&self) -> (Duration, Duration, Duration) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.created);
+        let busy = if self.depth > 0 {
+            self.busy + now.saturating_duration_since(self.last_entered)
+        } else {
+            self.busy
+        };
+        (elapsed, busy, elapsed.saturating_sub(busy))
+    }
+
+    /// Time since the span was created.
+    pub(crate) fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+}
+
+/// The metadata for an event emitted on behalf of a span.
+///
+/// Events are emitted at the level of the span by reusing its metadata, overriding the level
+/// needs metadata of its own, see `event_metadata`.
+pub(crate) fn span_event_metadata(
+    span: &'static Metadata<'static>,
+    level: Option<Level>,
+    fields: &'static [&'static str],
+) -> &'static Metadata<'static> {
+    match level {
+        Some(level) if level != *span.level() => event_metadata(span, level, fields),
+        _ => span,
+    }
+}
+
+/// Event metadata for an event emitted on behalf of a span, with the span's name, target and
+/// location but a level and fields of its own.
+///
+/// Metadata has to be `'static`, so it's leaked, once per span callsite, level and set of fields,
+/// which keeps this bounded by the number of callsites in the program. Each one gets a callsite of
+/// its own, so that the event isn't mistaken for the span it's emitted on behalf of.
+pub(crate) fn event_metadata(
+    span: &'static Metadata<'static>,
+    level: Level,
+    fields: &'static [&'static str],
+) -> &'static Metadata<'static> {
+    type Key = (Identifier, Level, usize);
+    static LEAKED: OnceLock<Mutex<HashMap<Key, &'static Metadata<'static>>>> = OnceLock::new();
+
+    let key = (span.callsite(), level, fields.as_ptr() as usize);
+    let mut leaked = LEAKED
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    let mut new_callsite = None;
+    let metadata = *leaked.entry(key).or_insert_with(|| {
+        let callsite: &'static EventCallsite = Box::leak(Box::default());
+        let metadata = Box::leak(Box::new(Metadata::new(
+            span.name(),
+            span.target(),
+            level,
+            span.file(),
+            span.line(),
+            span.module_path(),
+            FieldSet::new(fields, Identifier(callsite)),
+            Kind::EVENT,
+        )));
+        let _ = callsite.0.set(metadata);
+        new_callsite = Some(callsite);
+        metadata
+    });
+    drop(leaked);
+
+    // Registered once the lock is released, subscribers are told about it while registering.
+    if let Some(callsite) = new_callsite {
+        callsite::register(callsite);
+    }
+    metadata
+}
+
+// The callsite of leaked event metadata, like the ones the `tracing` macros declare.
+#[derive(Default)]
+struct EventCallsite(OnceLock<&'static Metadata<'static>>);
+
+impl Callsite for EventCallsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.0
+            .get()
+            .expect("metadata is set before the callsite is registered")
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use serde::{Serialize, Serializer};
+use tracing_core::Subscriber;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// An id for a span that, unlike its registry `Id`, isn't reused once the span closes.
+///
+/// Ids are unique within the process, and they're derived from a random seed so that ids from
+/// different processes are unlikely to collide. They're written as 16 hex digits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SpanUid(u64);
+
+impl SpanUid {
+    /// The id of the span, which is assigned the first time it's asked for and kept in the span's
+    /// extensions.
+    pub fn of<S>(span: &SpanRef<'_, S>) -> SpanUid
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    {
+        if let Some(uid) = span.extensions().get::<SpanUid>() {
+            return *uid;
+        }
+
+        // Another thread may have assigned it in the meantime.
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanUid>() {
+            Some(uid) => *uid,
+            None => {
+                let uid = SpanUid::next();
+                extensions.insert(uid);
+                uid
+            }
+        }
+    }
+
+    // A counter run through the SplitMix64 generator, its steps and finalizer are both bijective
+    // so an id can only repeat once the counter wraps around.
+    fn next() -> SpanUid {
+        static SEED: OnceLock<u64> = OnceLock::new();
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let seed = *SEED.get_or_init(|| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u32(std::process::id());
+            if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+                hasher.write_u128(now.as_nanos());
+            }
+            hasher.finish()
+        });
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        let mut z = seed.wrapping_add(count.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        SpanUid(z ^ (z >> 31))
+    }
+}
+
+impl fmt::Display for SpanUid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl Serialize for SpanUid {
+    fn serialize<M: Serializer>(&self, serializer: M) -> Result<M::Ok, M::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The ids needed to rebuild the tree of spans from the events logged in them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpanIds {
+    pub span_id: SpanUid,
+    /// Missing for a root span.
+    pub parent_span_id: Option<SpanUid>,
+    /// The same as `span_id` for a root span.
+    pub root_span_id: SpanUid,
+}
+
+impl SpanIds {
+    pub fn of<S>(span: &SpanRef<'_, S>) -> SpanIds
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    {
+        let span_id = SpanUid::of(span);
+        let parent_span_id = span.parent().map(|parent| SpanUid::of(&parent));
+        let root_span_id = match span.scope().from_root().next() {
+            Some(root) if parent_span_id.is_some() => SpanUid::of(&root),
+            _ => span_id,
+        };
+
+        SpanIds {
+            span_id,
+            parent_span_id,
+            root_span_id,
+        }
+    }
+}
@@ -0,0 +1,168 @@
+mod mock_writer;
+
+use std::io;
+use std::time::UNIX_EPOCH;
+
+use layer::fmt::binary::{BinaryFormatter, Decoder, Encoding};
+use layer::fmt::json::JsonFormatter;
+use layer::fmt::time::FixedClock;
+use layer::fmt::CollisionPolicy;
+use serde_json::{json, Value};
+use tracing::info;
+use tracing_subscriber::Registry;
+
+use crate::mock_writer::{fetch_link, run_layer, with_spans};
+
+fn json_formatter() -> JsonFormatter<Registry> {
+    JsonFormatter::new()
+        .with_clock(FixedClock(UNIX_EPOCH))
+        .with_collisions(CollisionPolicy::Nest)
+}
+
+// Drops the timings of span end events, which differ between runs.
+fn without_timings(mut records: Vec<Value>) -> Vec<Value> {
+    for record in &mut records {
+        let record = record.as_object_mut().unwrap();
+        for timing in ["elapsed", "busy", "idle"] {
+            record.remove(timing);
+        }
+    }
+    records
+}
+
+fn json_lines() -> Vec<Value> {
+    without_timings(run_layer(json_formatter(), with_spans, fetch_link).json())
+}
+
+fn decode(encoding: Encoding) -> Vec<Value> {
+    let formatter = BinaryFormatter::new(encoding).with_json_formatter(json_formatter());
+    let output = run_layer(formatter, with_spans, fetch_link).bytes();
+    without_timings(
+        Decoder::new(output.as_slice(), encoding)
+            .collect::<io::Result<_>>()
+            .unwrap(),
+    )
+}
+
+#[test]
+fn message_pack_records_match_json() {
+    let records = decode(Encoding::MessagePack);
+    assert_eq!(records.len(), 5);
+    assert_eq!(records, json_lines());
+}
+
+#[test]
+fn cbor_records_match_json() {
+    let records = decode(Encoding::Cbor);
+    assert_eq!(records.len(), 5);
+    assert_eq!(records, json_lines());
+}
+
+#[test]
+fn records_are_framed_by_their_length() {
+    let output = run_layer(BinaryFormatter::new(Encoding::Cbor), with_spans, || {
+        info!("First");
+        info!("Second");
+    })
+    .bytes();
+
+    let len = u32::from_be_bytes(output[..4].try_into().unwrap()) as usize;
+    let first: Value = ciborium::de::from_reader(&output[4..4 + len]).unwrap();
+    assert_eq!(first["title"], "First");
+
+    let rest = &output[4 + len..];
+    let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+    assert_eq!(rest.len(), 4 + len);
+    let second: Value = ciborium::de::from_reader(&rest[4..]).unwrap();
+    assert_eq!(second["title"], "Second");
+}
+
+#[test]
+fn decoder_writes_json_lines() {
+    let formatter =
+        BinaryFormatter::new(Encoding::MessagePack).with_json_formatter(json_formatter());
+    let output = run_layer(formatter, with_spans, || info!(count = 3, "Hello")).bytes();
+
+    let mut lines = Vec::new();
+    Decoder::new(output.as_slice(), Encoding::MessagePack)
+        .write_json_lines(&mut lines)
+        .unwrap();
+    let line: Value = serde_json::from_slice(&lines).unwrap();
+    assert_eq!(line["title"], "Hello");
+    assert_eq!(line["count"], json!(3));
+    assert!(lines.ends_with(b"}\n"));
+}
+
+#[test]
+fn decoder_skips_corrupt_records() {
+    let output = run_layer(
+        BinaryFormatter::new(Encoding::MessagePack),
+        with_spans,
+        || {
+            info!("First");
+            info!("Second");
+        },
+    )
+    .bytes();
+    let len = 4 + u32::from_be_bytes(output[..4].try_into().unwrap()) as usize;
+    let mut stream = output[..len].to_vec();
+    stream.extend_from_slice(&[0, 0, 0, 1, 0xc1]);
+    stream.extend_from_slice(&output[len..]);
+
+    let mut lines = Vec::new();
+    let skipped = Decoder::new(stream.as_slice(), Encoding::MessagePack)
+        .write_json_lines(&mut lines)
+        .unwrap();
+    assert_eq!(skipped, 1);
+    let titles: Vec<Value> = serde_json::Deserializer::from_slice(&lines)
+        .into_iter::<Value>()
+        .map(|line| line.unwrap()["title"].clone())
+        .collect();
+    assert_eq!(titles, ["First", "Second"]);
+}
+
+#[test]
+fn decoder_reports_truncated_streams() {
+    let output = run_layer(
+        BinaryFormatter::new(Encoding::MessagePack),
+        with_spans,
+        || info!("Hello"),
+    )
+    .bytes();
+
+    for cut in [2, output.len() - 1] {
+        let mut decoder = Decoder::new(&output[..cut], Encoding::MessagePack);
+        let error = decoder.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+    assert!(Decoder::new(&[][..], Encoding::MessagePack)
+        .next()
+        .is_none());
+}
+
+#[test]
+fn decoder_reports_corrupt_records() {
+    let stream: &[u8] = &[0, 0, 0, 1, 0xc1];
+    let error = Decoder::new(stream, Encoding::MessagePack)
+        .next()
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn integers_wider_than_64_bits_decode_as_strings() {
+    for encoding in [Encoding::MessagePack, Encoding::Cbor] {
+        let output = run_layer(BinaryFormatter::new(encoding), with_spans, || {
+            info!(small = 1i128, big = i128::MIN, huge = u128::MAX, "Hello")
+        })
+        .bytes();
+
+        let records = Decoder::new(output.as_slice(), encoding)
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records[0]["small"], 1);
+        assert_eq!(records[0]["big"], i128::MIN.to_string());
+        assert_eq!(records[0]["huge"], u128::MAX.to_string());
+    }
+}
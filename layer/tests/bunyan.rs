@@ -0,0 +1,140 @@
+mod mock_writer;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use layer::fmt::bunyan::BunyanFormatter;
+use layer::fmt::time::FixedClock;
+use layer::span_events::{DurationFields, SpanEvents};
+use tracing::{debug, info_span, warn};
+use tracing_subscriber::Registry;
+
+use crate::mock_writer::run_layer;
+
+fn formatter() -> BunyanFormatter<Registry> {
+    BunyanFormatter::new("cats")
+        .with_hostname("cat-host")
+        .with_clock(FixedClock(UNIX_EPOCH + Duration::from_millis(1_500)))
+}
+
+#[test]
+fn records_have_the_bunyan_core_fields() {
+    let output = run_layer(
+        formatter(),
+        |layer| layer,
+        || {
+            let _enter = info_span!("get_cat", correlation_id = "abc").entered();
+            warn!(attempt = 2, "Retrying link");
+        },
+    )
+    .json();
+
+    let record = &output[0];
+    assert_eq!(record["v"], 0);
+    assert_eq!(record["name"], "cats");
+    assert_eq!(record["msg"], "Retrying link");
+    assert_eq!(record["level"], 40);
+    assert_eq!(record["hostname"], "cat-host");
+    assert_eq!(record["pid"], std::process::id());
+    assert_eq!(record["time"], "1970-01-01T00:00:01.500Z");
+    assert_eq!(record["target"], "bunyan");
+    assert_eq!(record["correlation_id"], "abc");
+    assert_eq!(record["attempt"], 2);
+}
+
+#[test]
+fn levels_are_numeric() {
+    let output = run_layer(
+        formatter(),
+        |layer| layer,
+        || {
+            tracing::trace!("a");
+            debug!("b");
+            tracing::info!("c");
+            warn!("d");
+            tracing::error!("e");
+        },
+    )
+    .json();
+
+    let levels: Vec<_> = output
+        .iter()
+        .map(|r| r["level"].as_u64().unwrap())
+        .collect();
+    assert_eq!(levels, [10, 20, 30, 40, 50]);
+}
+
+#[test]
+fn fields_named_like_bunyan_entries_are_prefixed() {
+    let output = run_layer(
+        formatter(),
+        |layer| layer,
+        || {
+            let _enter = info_span!("get_cat", name = "tabby", v = 3).entered();
+            warn!(hostname = "spoofed", target = "mouse", line = 7, "hi");
+        },
+    )
+    .json();
+
+    let record = output[0].as_object().unwrap();
+    assert_eq!(record["name"], "cats");
+    assert_eq!(record["v"], 0);
+    assert_eq!(record["hostname"], "cat-host");
+    assert_eq!(record["target"], "bunyan");
+    assert!(record["line"].is_u64());
+    assert_eq!(record["fields.name"], "tabby");
+    assert_eq!(record["fields.v"], 3);
+    assert_eq!(record["fields.hostname"], "spoofed");
+    assert_eq!(record["fields.target"], "mouse");
+    assert_eq!(record["fields.line"], 7);
+}
+
+#[test]
+fn span_events_follow_the_bunyan_conventions() {
+    let output = run_layer(
+        formatter(),
+        |layer| layer.with_spans(true),
+        || {
+            info_span!("get_cat", correlation_id = "abc").in_scope(|| {
+                std::thread::sleep(Duration::from_millis(5));
+            });
+        },
+    )
+    .json();
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0]["msg"], "[SPAN_START]");
+    assert_eq!(output[0]["span"], "get_cat");
+    assert_eq!(output[0]["correlation_id"], "abc");
+    assert!(output[0].get("elapsed_milliseconds").is_none());
+    assert_eq!(output[1]["msg"], "[SPAN_END]");
+    assert_eq!(output[1]["span"], "get_cat");
+    assert_eq!(output[1]["correlation_id"], "abc");
+    assert!(output[1]["elapsed_milliseconds"].as_u64().unwrap() >= 5);
+}
+
+#[test]
+fn elapsed_milliseconds_uses_numeric_durations_when_there_are_some() {
+    let output = run_layer(
+        formatter(),
+        |layer| {
+            layer
+                .with_span_events(SpanEvents::CLOSE | SpanEvents::ENTER)
+                .with_duration_fields(DurationFields::NANOS)
+        },
+        || {
+            info_span!("get_cat").in_scope(|| {
+                std::thread::sleep(Duration::from_millis(5));
+            });
+        },
+    )
+    .json();
+
+    assert_eq!(output.len(), 2);
+    // Only the start and end of spans are renamed.
+    assert_eq!(output[0]["msg"], "enter");
+    assert_eq!(output[1]["msg"], "[SPAN_END]");
+    assert_eq!(
+        output[1]["elapsed_milliseconds"].as_u64().unwrap(),
+        output[1]["elapsed_ns"].as_u64().unwrap() / 1_000_000
+    );
+}
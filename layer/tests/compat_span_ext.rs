@@ -0,0 +1,121 @@
+mod mock_writer;
+
+use std::convert::identity;
+
+use layer::compat_span_ext::{CompatSpanExt, Precedence};
+use layer::fmt::json::JsonFormatter;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, span, Level};
+
+use crate::mock_writer::run_layer;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Tenant {
+    id: u64,
+    name: String,
+}
+
+#[test]
+fn stored_values_are_typed() {
+    run_layer(JsonFormatter::new(), identity, || {
+        let span = span!(Level::INFO, "request", attempt = 3);
+        let tenant = Tenant {
+            id: 1,
+            name: String::from("cats"),
+        };
+        span.set_stored("tenant", &tenant).unwrap();
+
+        assert_eq!(
+            span.get_stored_as::<Tenant>("tenant", Precedence::Nearest),
+            Some(tenant)
+        );
+        assert_eq!(
+            span.get_stored_as::<u32>("attempt", Precedence::Nearest),
+            Some(3)
+        );
+        assert_eq!(
+            span.get_stored_as::<String>("attempt", Precedence::Nearest),
+            None
+        );
+    });
+}
+
+#[test]
+fn precedence_picks_nearest_or_root_most() {
+    run_layer(JsonFormatter::new(), identity, || {
+        let outer = span!(Level::INFO, "outer", id = "outer");
+        let _enter = outer.enter();
+        let inner = span!(Level::INFO, "inner", id = "inner");
+
+        assert_eq!(
+            inner.get_stored_as::<String>("id", Precedence::Nearest),
+            Some(String::from("inner"))
+        );
+        assert_eq!(
+            inner.get_stored_as::<String>("id", Precedence::RootMost),
+            Some(String::from("outer"))
+        );
+        assert_eq!(inner.get_stored::<Value>("id"), Some(Value::from("outer")));
+    });
+}
+
+#[test]
+fn set_values_propagate_to_descendants_log_lines() {
+    let output = run_layer(JsonFormatter::new(), identity, || {
+        let outer = span!(Level::INFO, "outer");
+        let _enter = outer.enter();
+        outer
+            .set_stored(String::from("correlation_id"), "abc")
+            .unwrap();
+        let inner = span!(Level::INFO, "inner");
+        let _enter = inner.enter();
+        info!("shaving yaks");
+    })
+    .json();
+
+    assert_eq!(output[0]["correlation_id"], "abc");
+}
+
+#[test]
+fn remove_only_affects_the_span_itself() {
+    run_layer(JsonFormatter::new(), identity, || {
+        let outer = span!(Level::INFO, "outer", id = "outer", user = "olly");
+        let _enter = outer.enter();
+        let inner = span!(Level::INFO, "inner", id = "inner");
+
+        assert_eq!(inner.remove_stored("id"), Some(Value::from("inner")));
+        assert_eq!(inner.remove_stored("user"), None);
+        assert_eq!(
+            inner.get_stored_as::<String>("id", Precedence::Nearest),
+            Some(String::from("outer"))
+        );
+    });
+}
+
+#[test]
+fn all_stored_values_are_merged() {
+    run_layer(JsonFormatter::new(), identity, || {
+        let outer = span!(Level::INFO, "outer", id = "outer", user = "olly");
+        let _enter = outer.enter();
+        let inner = span!(Level::INFO, "inner", id = "inner", depth = 2);
+
+        let nearest = inner.get_all_stored(Precedence::Nearest);
+        assert_eq!(nearest.len(), 3);
+        assert_eq!(nearest["id"], "inner");
+        assert_eq!(nearest["user"], "olly");
+        assert_eq!(nearest["depth"], 2);
+
+        let root_most = inner.get_all_stored(Precedence::RootMost);
+        assert_eq!(root_most["id"], "outer");
+    });
+}
+
+#[test]
+fn nothing_is_stored_without_the_layer() {
+    let span = span!(Level::INFO, "request", id = 1);
+
+    span.set_stored("tenant", "cats").unwrap();
+    assert_eq!(span.get_stored_as::<u64>("id", Precedence::Nearest), None);
+    assert!(span.get_all_stored(Precedence::Nearest).is_empty());
+}
@@ -0,0 +1,140 @@
+mod mock_writer;
+
+use std::thread;
+use std::time::Duration;
+
+use layer::fmt::json::JsonFormatter;
+use layer::fmt::{format_duration, parse_duration};
+use layer::span_events::{DurationFields, SpanEvents};
+use proptest::prelude::*;
+use serde_json::Value;
+use tracing::info_span;
+
+use crate::mock_writer::run_layer;
+
+fn close_event(fields: DurationFields) -> Value {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| {
+            layer
+                .with_span_events(SpanEvents::CLOSE)
+                .with_duration_fields(fields)
+        },
+        || info_span!("work").in_scope(|| thread::sleep(Duration::from_millis(5))),
+    );
+    output.json().into_iter().next().unwrap()
+}
+
+fn has(event: &Value, suffix: &str) -> bool {
+    ["elapsed", "busy", "idle"]
+        .iter()
+        .all(|name| event.get(format!("{}{}", name, suffix)).is_some())
+}
+
+#[test]
+fn durations_are_text_by_default() {
+    let event = close_event(DurationFields::default());
+
+    assert!(has(&event, ""));
+    assert!(!has(&event, "_ns"));
+    assert!(!has(&event, "_ms"));
+}
+
+#[test]
+fn durations_can_be_numeric() {
+    let event = close_event(DurationFields::NANOS | DurationFields::MILLIS);
+
+    assert!(!has(&event, ""));
+    assert!(has(&event, "_ns"));
+    assert!(has(&event, "_ms"));
+
+    let nanos = event["busy_ns"].as_u64().unwrap();
+    let millis = event["busy_ms"].as_f64().unwrap();
+    assert!(nanos >= 5_000_000);
+    assert!((nanos as f64 / 1e6 - millis).abs() < 1e-6);
+}
+
+#[test]
+fn text_and_numeric_durations_agree() {
+    let event = close_event(DurationFields::TEXT | DurationFields::NANOS);
+
+    let text = parse_duration(event["elapsed"].as_str().unwrap()).unwrap();
+    let nanos = Duration::from_nanos(event["elapsed_ns"].as_u64().unwrap());
+    assert_eq!(event["elapsed"], format_duration(nanos));
+    assert!(text.abs_diff(nanos) <= nanos / 1000);
+}
+
+#[test]
+fn parse_duration_reads_every_unit() {
+    assert_eq!(parse_duration("999ns"), Some(Duration::from_nanos(999)));
+    assert_eq!(parse_duration("1.500us"), Some(Duration::from_nanos(1_500)));
+    assert_eq!(parse_duration("1.500µs"), Some(Duration::from_nanos(1_500)));
+    assert_eq!(
+        parse_duration("12.30ms"),
+        Some(Duration::from_micros(12_300))
+    );
+    assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+    assert_eq!(parse_duration("3s"), Some(Duration::from_secs(3)));
+    assert_eq!(
+        parse_duration("18446744073s"),
+        Some(Duration::from_secs(18_446_744_073))
+    );
+}
+
+#[test]
+fn parse_duration_rejects_anything_else() {
+    for text in [
+        "", "ms", "12", "12m", "12 h", "-1s", "NaNs", "infs", "1e400s",
+    ] {
+        assert_eq!(parse_duration(text), None, "{}", text);
+    }
+}
+
+// Uniformly random `u64`s are almost all hundreds of years long, shifting them spreads the
+// durations across every unit.
+fn nanos() -> impl Strategy<Value = u64> {
+    (any::<u64>(), 0..64u32).prop_map(|(nanos, shift)| nanos >> shift)
+}
+
+proptest! {
+    #[test]
+    fn parse_inverts_format_to_four_significant_digits(nanos in nanos()) {
+        let duration = Duration::from_nanos(nanos);
+        let parsed = parse_duration(&format_duration(duration)).unwrap();
+
+        // Half a unit in the fourth significant digit, plus some slack for floating point.
+        let tolerance = duration / 2000 + Duration::from_nanos(1);
+        prop_assert!(
+            parsed.abs_diff(duration) <= tolerance,
+            "{:?} was formatted as {} and parsed as {:?}",
+            duration,
+            format_duration(duration),
+            parsed
+        );
+    }
+
+    #[test]
+    fn format_is_stable_after_a_round_trip(nanos in nanos()) {
+        let text = format_duration(Duration::from_nanos(nanos));
+        let parsed = parse_duration(&text).unwrap();
+        let reparsed = parse_duration(&format_duration(parsed)).unwrap();
+
+        prop_assert!(
+            parsed.abs_diff(reparsed) <= parsed / 2000 + Duration::from_nanos(1),
+            "{} was parsed as {:?} and reparsed as {:?}",
+            text,
+            parsed,
+            reparsed
+        );
+    }
+
+    #[test]
+    fn whole_seconds_round_trip_exactly(secs in any::<u64>()) {
+        let duration = Duration::from_secs(secs);
+        let text = format_duration(duration);
+
+        if !text.contains('.') {
+            prop_assert_eq!(parse_duration(&text), Some(duration));
+        }
+    }
+}
@@ -1,15 +1,21 @@
 mod mock_writer;
 
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 
 use layer::compat_layer::CompatLayer;
 use layer::fmt::json::JsonFormatter;
+use layer::fmt::time::{FixedClock, TimestampFormat};
+use layer::fmt::CollisionPolicy;
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
 use serde_json::Value;
 use tracing::{info, span};
 use tracing_core::Level;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
 
-use crate::mock_writer::MockWriter;
+use crate::mock_writer::{capture, run_layer, with_spans, MakeMockWriter, MockWriter};
 
 // Run a closure and collect the output emitted by the tracing instrumentation using an in-memory
 // buffer.
@@ -66,3 +72,328 @@ fn each_line_is_valid_json() {
 fn see_output() {
     let _output = run_and_get_output(test_action);
 }
+
+// 2023-06-01T12:30:00.123456789Z
+fn fixed_clock() -> FixedClock {
+    FixedClock(UNIX_EPOCH + Duration::new(1_685_622_600, 123_456_789))
+}
+
+fn timestamps(formatter: JsonFormatter<Registry>) -> Vec<Value> {
+    run_layer(formatter, with_spans, test_action)
+        .json()
+        .into_iter()
+        .map(|line| line["timestamp"].clone())
+        .collect()
+}
+
+#[test]
+fn timestamp_is_rfc3339_utc_by_default() {
+    let timestamps = timestamps(JsonFormatter::new().with_clock(fixed_clock()));
+
+    assert!(!timestamps.is_empty());
+    for timestamp in timestamps {
+        assert_eq!(timestamp, "2023-06-01T12:30:00.123456Z");
+    }
+}
+
+#[test]
+fn timestamp_as_epoch_millis_and_nanos() {
+    let millis = timestamps(
+        JsonFormatter::new()
+            .with_clock(fixed_clock())
+            .with_timestamp(TimestampFormat::EpochMillis),
+    );
+    assert!(millis.iter().all(|t| *t == 1_685_622_600_123_u64));
+
+    let nanos = timestamps(
+        JsonFormatter::new()
+            .with_clock(fixed_clock())
+            .with_timestamp(TimestampFormat::EpochNanos),
+    );
+    assert!(nanos.iter().all(|t| *t == 1_685_622_600_123_456_789_u64));
+}
+
+#[test]
+fn timestamp_can_be_omitted() {
+    let formatter = JsonFormatter::new().without_timestamp();
+    for line in run_layer(formatter, with_spans, test_action).json() {
+        assert!(line.get("timestamp").is_none());
+    }
+}
+
+// Unlike `Value`, which keeps one of the values of a duplicated key, this keeps every key.
+struct Keys(Vec<String>);
+
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeysVisitor;
+
+        impl<'de> Visitor<'de> for KeysVisitor {
+            type Value = Keys;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Keys, A::Error> {
+                let mut keys = Vec::new();
+                while let Some((key, IgnoredAny)) = map.next_entry::<String, IgnoredAny>()? {
+                    keys.push(key);
+                }
+                Ok(Keys(keys))
+            }
+        }
+
+        deserializer.deserialize_map(KeysVisitor)
+    }
+}
+
+fn colliding_action() {
+    let outer = span!(Level::INFO, "outer", id = "outer", user = "olly");
+    let _enter = outer.enter();
+    let inner = span!(Level::INFO, "inner", id = "inner");
+    let _enter = inner.enter();
+    info!(id = "event", "colliding");
+}
+
+fn run_with_collisions(policy: CollisionPolicy) -> Value {
+    run_action_with_collisions(policy, colliding_action)
+}
+
+fn run_action_with_collisions<F: Fn()>(policy: CollisionPolicy, action: F) -> Value {
+    let formatter = JsonFormatter::new().with_collisions(policy);
+    let output = run_layer(formatter, with_spans, action).text();
+    let line = output.lines().find(|l| l.contains("colliding")).unwrap();
+
+    let Keys(keys) = serde_json::from_str(line).unwrap();
+    let mut deduped = keys.clone();
+    deduped.sort();
+    deduped.dedup();
+    assert_eq!(keys.len(), deduped.len(), "duplicate keys in {}", line);
+
+    serde_json::from_str(line).unwrap()
+}
+
+#[test]
+fn innermost_wins_collisions() {
+    let line = run_with_collisions(CollisionPolicy::InnermostWins);
+
+    assert_eq!(line["id"], "event");
+    assert_eq!(line["user"], "olly");
+}
+
+#[test]
+fn outermost_wins_collisions() {
+    let line = run_with_collisions(CollisionPolicy::OutermostWins);
+
+    assert_eq!(line["id"], "outer");
+    assert_eq!(line["user"], "olly");
+}
+
+#[test]
+fn prefix_collisions_with_span_name() {
+    let line = run_with_collisions(CollisionPolicy::Prefix);
+
+    assert_eq!(line["outer.id"], "outer");
+    assert_eq!(line["outer.user"], "olly");
+    assert_eq!(line["inner.id"], "inner");
+    assert_eq!(line["id"], "event");
+}
+
+#[test]
+fn nest_span_fields() {
+    let line = run_with_collisions(CollisionPolicy::Nest);
+
+    assert_eq!(line["id"], "event");
+    assert_eq!(
+        line["spans"],
+        serde_json::json!([
+            { "name": "outer", "fields": { "id": "outer", "user": "olly" } },
+            { "name": "inner", "fields": { "id": "inner" } },
+        ])
+    );
+}
+
+#[test]
+fn nest_prefixes_an_event_field_named_spans() {
+    let line = run_action_with_collisions(CollisionPolicy::Nest, || {
+        let _span = span!(Level::INFO, "outer", id = "outer").entered();
+        info!(spans = 3, "colliding");
+    });
+
+    assert_eq!(line["fields.spans"], 3);
+    assert_eq!(line["spans"][0]["fields"]["id"], "outer");
+}
+
+#[test]
+fn fields_named_like_header_keys_are_prefixed() {
+    for policy in [
+        CollisionPolicy::InnermostWins,
+        CollisionPolicy::OutermostWins,
+        CollisionPolicy::Prefix,
+        CollisionPolicy::Nest,
+    ] {
+        let line = run_action_with_collisions(policy, || {
+            let _span = span!(Level::INFO, "outer", level = "span").entered();
+            info!(
+                timestamp = 1,
+                title = "event",
+                span = "event",
+                source.line = 2,
+                "colliding"
+            );
+        });
+
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["title"], "colliding");
+        assert_eq!(line["span"], "outer");
+        assert!(line["timestamp"].is_string(), "{}", line);
+        assert!(line["source.line"].as_u64().unwrap() > 2, "{}", line);
+        assert_eq!(line["fields.timestamp"], 1);
+        assert_eq!(line["fields.title"], "event");
+        assert_eq!(line["fields.span"], "event");
+        assert_eq!(line["fields.source.line"], 2);
+        match policy {
+            CollisionPolicy::Prefix => assert_eq!(line["outer.level"], "span"),
+            CollisionPolicy::Nest => assert_eq!(line["spans"][0]["fields"]["level"], "span"),
+            _ => assert_eq!(line["fields.level"], "span"),
+        }
+    }
+}
+
+#[test]
+fn only_keys_the_formatter_writes_are_prefixed() {
+    let formatter = JsonFormatter::new().without_timestamp();
+    let output = run_layer(formatter, with_spans, || {
+        info!(timestamp = 1, root_span_id = "mine", "colliding");
+    });
+    let line = &output.json()[0];
+    assert_eq!(line["timestamp"], 1);
+    assert_eq!(line["root_span_id"], "mine");
+
+    let formatter = JsonFormatter::new().with_span_ids(true);
+    let output = run_layer(formatter, with_spans, || {
+        let _span = span!(Level::INFO, "outer").entered();
+        info!(timestamp = 1, root_span_id = "mine", "colliding");
+    });
+    let line = &output.json()[1];
+    assert!(line["timestamp"].is_string(), "{}", line);
+    assert!(line["root_span_id"].is_string(), "{}", line);
+    assert_eq!(line["fields.timestamp"], 1);
+    assert_eq!(line["fields.root_span_id"], "mine");
+}
+
+// Span fields are rendered once and reused for later events, these check that they are rendered
+// again when they change.
+fn lines_with_title(output: &str, title: &str) -> Vec<Value> {
+    output
+        .lines()
+        .map(|line| {
+            let Keys(keys) = serde_json::from_str(line).unwrap();
+            let mut deduped = keys.clone();
+            deduped.sort();
+            deduped.dedup();
+            assert_eq!(keys.len(), deduped.len(), "duplicate keys in {}", line);
+            serde_json::from_str::<Value>(line).unwrap()
+        })
+        .filter(|line| line["title"] == title)
+        .collect()
+}
+
+#[test]
+fn fields_recorded_on_ancestors_show_up_in_later_events() {
+    let output = run_layer(JsonFormatter::new(), with_spans, || {
+        let outer = span!(Level::INFO, "outer", status = tracing::field::Empty);
+        let _enter = outer.enter();
+        let inner = span!(Level::INFO, "inner", id = "inner");
+        let _enter = inner.enter();
+        info!("working");
+        outer.record("status", 200);
+        info!("working");
+        inner.record("id", "changed");
+        info!("working");
+    })
+    .text();
+    let lines = lines_with_title(&output, "working");
+
+    assert!(lines[0].get("status").is_none());
+    assert_eq!(lines[0]["id"], "inner");
+    assert_eq!(lines[1]["status"], 200);
+    assert_eq!(lines[1]["id"], "inner");
+    assert_eq!(lines[2]["status"], 200);
+    assert_eq!(lines[2]["id"], "changed");
+}
+
+#[test]
+fn stored_values_show_up_in_later_events() {
+    use layer::compat_span_ext::CompatSpanExt;
+
+    let output = run_layer(JsonFormatter::new(), with_spans, || {
+        let outer = span!(Level::INFO, "outer");
+        let _enter = outer.enter();
+        let _inner = span!(Level::INFO, "inner").entered();
+        info!("working");
+        outer.set_stored("correlation_id", "abc").unwrap();
+        info!("working");
+        outer.remove_stored("correlation_id");
+        info!("working");
+    })
+    .text();
+    let lines = lines_with_title(&output, "working");
+
+    assert!(lines[0].get("correlation_id").is_none());
+    assert_eq!(lines[1]["correlation_id"], "abc");
+    assert!(lines[2].get("correlation_id").is_none());
+}
+
+#[test]
+fn event_fields_are_resolved_against_rendered_span_fields() {
+    for (policy, id) in [
+        (CollisionPolicy::InnermostWins, "event"),
+        (CollisionPolicy::OutermostWins, "outer"),
+    ] {
+        let formatter = JsonFormatter::new().with_collisions(policy);
+        let output = run_layer(formatter, with_spans, || {
+            let _outer = span!(Level::INFO, "outer", id = "outer").entered();
+            info!(other = 1, "working");
+            info!(id = "event", "working");
+        })
+        .text();
+        let lines = lines_with_title(&output, "working");
+
+        assert_eq!(lines[0]["id"], "outer");
+        assert_eq!(lines[0]["other"], 1);
+        assert_eq!(lines[1]["id"], id);
+    }
+}
+
+#[test]
+fn differently_configured_layers_dont_share_rendered_span_fields() {
+    let other = MakeMockWriter::default();
+    let output = capture(
+        |writer| {
+            tracing_subscriber::registry()
+                .with(CompatLayer::new(JsonFormatter::new(), writer))
+                .with(CompatLayer::new(
+                    JsonFormatter::new().without_timestamp().with_span_ids(true),
+                    other.clone(),
+                ))
+        },
+        || {
+            let _outer = span!(Level::INFO, "outer", timestamp = 1, parent_span_id = 2).entered();
+            info!("working");
+            info!("working");
+        },
+    );
+    let lines = lines_with_title(&output, "working");
+    let other = lines_with_title(&other.text(), "working");
+
+    for line in &lines {
+        assert_eq!(line["fields.timestamp"], 1);
+        assert_eq!(line["parent_span_id"], 2);
+    }
+    for line in &other {
+        assert_eq!(line["timestamp"], 1);
+        assert_eq!(line["fields.parent_span_id"], 2);
+    }
+}
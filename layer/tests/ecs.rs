@@ -0,0 +1,172 @@
+mod mock_writer;
+
+use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
+
+use layer::fmt::ecs::{EcsFormatter, ECS_VERSION};
+use layer::fmt::time::FixedClock;
+use layer::fmt::CollisionPolicy;
+use serde_json::Value;
+use tracing::{info, info_span};
+use tracing_subscriber::Registry;
+
+use crate::mock_writer::{fetch_link, run_layer, with_spans};
+
+// The ECS field definitions that the formatter uses, taken from the `fields.csv` that is
+// published with each version of ECS, as field name to type.
+fn ecs_fields() -> HashMap<String, String> {
+    let csv = include_str!("ecs/fields.csv");
+    let mut lines = csv.lines();
+    let header: Vec<_> = lines.next().unwrap().split(',').collect();
+    let field = header.iter().position(|&c| c == "Field").unwrap();
+    let kind = header.iter().position(|&c| c == "Type").unwrap();
+
+    lines
+        .map(|line| {
+            // None of the columns before `Type` are quoted.
+            let columns: Vec<_> = line.splitn(kind + 2, ',').collect();
+            (columns[field].to_string(), columns[kind].to_string())
+        })
+        .collect()
+}
+
+// Flattens a record into dotted field names, stopping at `object` fields which can hold anything.
+// Labels are keywords, whatever their names.
+fn flatten<'a>(
+    prefix: &str,
+    value: &'a Value,
+    fields: &HashMap<String, String>,
+    out: &mut Vec<(String, &'a Value)>,
+) {
+    match value {
+        Value::Object(labels) if prefix == "labels" => {
+            for value in labels.values() {
+                out.push((String::from("labels.*"), value));
+            }
+        }
+        Value::Object(map) if fields.get(prefix).map(String::as_str) != Some("object") => {
+            for (key, value) in map {
+                let name = match prefix {
+                    "" => key.clone(),
+                    prefix => format!("{}.{}", prefix, key),
+                };
+                flatten(&name, value, fields, out);
+            }
+        }
+        value => out.push((prefix.to_string(), value)),
+    }
+}
+
+fn matches_type(kind: &str, value: &Value) -> bool {
+    match kind {
+        "keyword" | "match_only_text" | "wildcard" => value.is_string(),
+        "long" => value.is_u64() || value.is_i64(),
+        "date" => value
+            .as_str()
+            .is_some_and(|date| chrono::DateTime::parse_from_rfc3339(date).is_ok()),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+#[test]
+fn records_only_use_ecs_fields_with_the_right_types() {
+    let fields = ecs_fields();
+    let output = run_layer(EcsFormatter::new(), with_spans, || {
+        fetch_link();
+        info!(user.id = 7, user.admin = true, "Logged in");
+    })
+    .json();
+    assert_eq!(output.len(), 6);
+
+    for record in &output {
+        let mut flat = Vec::new();
+        flatten("", record, &fields, &mut flat);
+        for (name, value) in flat {
+            let kind = match name.as_str() {
+                "labels.*" => "keyword",
+                name => fields
+                    .get(name)
+                    .unwrap_or_else(|| panic!("{} isn't an ECS field", name)),
+            };
+            assert!(
+                matches_type(kind, value),
+                "{} should be a {} but is {}",
+                name,
+                kind,
+                value
+            );
+        }
+    }
+}
+
+#[test]
+fn records_have_the_expected_values() {
+    let formatter = EcsFormatter::new().with_clock(FixedClock(UNIX_EPOCH));
+    let output = run_layer(formatter, with_spans, fetch_link).json();
+    let record = &output[2];
+
+    assert_eq!(record["@timestamp"], "1970-01-01T00:00:00.000000Z");
+    assert_eq!(record["ecs"]["version"], ECS_VERSION);
+    assert_eq!(record["log"]["level"], "INFO");
+    assert_eq!(record["log"]["logger"], "ecs::mock_writer");
+    assert_eq!(
+        record["log"]["origin"]["file"]["name"],
+        file!().replace("ecs.rs", "mock_writer.rs")
+    );
+    assert!(record["log"]["origin"]["file"]["line"].is_u64());
+    assert_eq!(record["message"], "Fetching link!");
+    assert_eq!(record["process"]["pid"], std::process::id());
+    assert_eq!(
+        record["labels"],
+        serde_json::json!({
+            "correlation_id": "abc",
+            "attempt": "1",
+            "cached": "true",
+            "ratio": "0.5",
+            "url": "https://cats",
+        })
+    );
+}
+
+#[test]
+fn labels_are_flat_strings() {
+    let formatter = EcsFormatter::new().with_collisions(CollisionPolicy::Prefix);
+    let output = run_layer(formatter, with_spans, || {
+        let _cat = info_span!("get_cat", correlation_id = "abc").entered();
+        info!(user.id = 7, ratio = 0.5, "Logged in");
+    })
+    .json();
+
+    assert_eq!(
+        output[1]["labels"],
+        serde_json::json!({ "get_cat_correlation_id": "abc", "user_id": "7", "ratio": "0.5" })
+    );
+}
+
+#[test]
+#[should_panic(expected = "collides with the fields of the record")]
+fn namespaces_cant_collide_with_the_record() {
+    let _ = EcsFormatter::<Registry>::new().with_namespace("log.fields");
+}
+
+#[test]
+fn fields_can_go_under_another_namespace() {
+    let formatter = EcsFormatter::new()
+        .with_namespace("cats.context")
+        .with_collisions(CollisionPolicy::Nest);
+    let output = run_layer(formatter, with_spans, fetch_link).json();
+    let record = &output[2];
+
+    assert!(record.get("labels").is_none());
+    assert_eq!(
+        record["cats"]["context"],
+        serde_json::json!({
+            "get_cat.correlation_id": "abc",
+            "get_cat_link.attempt": 1,
+            "cached": true,
+            "ratio": 0.5,
+            "url": "https://cats",
+        })
+    );
+}
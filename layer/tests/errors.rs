@@ -0,0 +1,151 @@
+mod mock_writer;
+
+use std::error::Error;
+use std::fmt;
+
+use layer::error::ErrorReport;
+use layer::fmt::json::JsonFormatter;
+use layer::redaction::{Action, Redaction};
+use regex::Regex;
+use serde_json::json;
+use tracing::{error, warn};
+
+use crate::mock_writer::run_layer;
+
+#[derive(Debug)]
+struct FetchError(TimeoutError);
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to fetch cat")
+    }
+}
+
+impl Error for FetchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[derive(Debug)]
+struct TimeoutError;
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("timed out after 5s talking to olly@cats.com")
+    }
+}
+
+impl Error for TimeoutError {}
+
+#[test]
+fn errors_are_recorded_with_their_sources() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer,
+        || error!(error = &FetchError(TimeoutError) as &dyn Error, "Failed"),
+    )
+    .json();
+
+    assert_eq!(
+        output[0]["error"],
+        json!({
+            "message": "failed to fetch cat",
+            "sources": ["timed out after 5s talking to olly@cats.com"],
+        })
+    );
+}
+
+#[test]
+fn reports_include_the_type_of_the_error() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer,
+        || {
+            let error = FetchError(TimeoutError);
+            error!(error = ErrorReport::new(&error).as_value(), "Failed");
+        },
+    )
+    .json();
+
+    assert_eq!(
+        output[0]["error"],
+        json!({
+            "message": "failed to fetch cat",
+            "sources": ["timed out after 5s talking to olly@cats.com"],
+            "type": "errors::FetchError",
+        })
+    );
+}
+
+#[test]
+fn backtraces_are_captured_for_error_events() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_error_backtraces(true),
+        || {
+            error!(error = &TimeoutError as &dyn Error, "Failed");
+            warn!(error = &TimeoutError as &dyn Error, "Retrying");
+        },
+    )
+    .json();
+
+    let backtrace = output[0]["error"]["backtrace"].as_str().unwrap();
+    assert!(backtrace.contains("backtraces_are_captured_for_error_events"));
+    assert!(output[1]["error"].get("backtrace").is_none());
+}
+
+#[test]
+fn backtraces_are_off_by_default() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer,
+        || error!(error = &TimeoutError as &dyn Error, "Failed"),
+    )
+    .json();
+
+    assert!(output[0]["error"].get("backtrace").is_none());
+}
+
+#[test]
+fn error_messages_are_redacted() {
+    let email = Regex::new(r"[\w.+-]+@[\w-]+\.[\w.]+").unwrap();
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_redaction(Redaction::new().value(email, Action::redact())),
+        || error!(error = &FetchError(TimeoutError) as &dyn Error, "Failed"),
+    )
+    .json();
+
+    assert_eq!(
+        output[0]["error"]["sources"],
+        json!(["timed out after 5s talking to [REDACTED]"])
+    );
+}
+
+#[cfg(feature = "anyhow")]
+#[test]
+fn anyhow_errors_are_recorded_with_their_context() {
+    use anyhow::Context;
+
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer,
+        || {
+            let error = Err::<(), _>(FetchError(TimeoutError))
+                .context("failed to get a link")
+                .unwrap_err();
+            error!(error = ErrorReport::from(&error).as_value(), "Failed");
+        },
+    )
+    .json();
+
+    assert_eq!(output[0]["error"]["message"], "failed to get a link");
+    assert_eq!(
+        output[0]["error"]["sources"],
+        json!([
+            "failed to fetch cat",
+            "timed out after 5s talking to olly@cats.com"
+        ])
+    );
+}
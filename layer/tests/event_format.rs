@@ -0,0 +1,129 @@
+mod mock_writer;
+
+use std::convert::identity;
+
+use layer::compat_span_ext::CompatSpanExt;
+use layer::fmt::event_format::{AsFormatEvent, FromFormatEvent};
+use layer::fmt::json::JsonFormatter;
+use layer::recorder::FieldRecorder;
+use layer::redaction::{Action, Redaction};
+use serde_json::Value;
+use tracing::{info, span, Level};
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::mock_writer::{capture_json, run_layer};
+
+#[test]
+fn format_can_be_used_by_fmt_layer() {
+    let output = capture_json(
+        |writer| {
+            tracing_subscriber::registry()
+                .with(FieldRecorder::new())
+                .with(
+                    tracing_subscriber::fmt::layer()
+                        .event_format(AsFormatEvent::new(JsonFormatter::new()))
+                        .with_writer(writer),
+                )
+        },
+        || {
+            let span = span!(Level::INFO, "outer", correlation_id = "abc");
+            let _enter = span.enter();
+            let span = span!(Level::INFO, "inner", depth = 2);
+            let _enter = span.enter();
+            info!(answer = 42, "shaving yaks");
+        },
+    );
+
+    assert_eq!(output.len(), 1);
+    let line = &output[0];
+    assert_eq!(line["title"], "shaving yaks");
+    assert_eq!(line["span"], "inner");
+    assert_eq!(line["correlation_id"], "abc");
+    assert_eq!(line["depth"], 2);
+    assert_eq!(line["answer"], 42);
+}
+
+#[test]
+fn fmt_layer_events_are_redacted_by_the_field_recorder() {
+    let output = capture_json(
+        |writer| {
+            let recorder = FieldRecorder::new()
+                .with_redaction(Redaction::new().name("password", Action::redact()));
+            let event_format = recorder.event_format(JsonFormatter::new());
+            tracing_subscriber::registry().with(recorder).with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(event_format)
+                    .with_writer(writer),
+            )
+        },
+        || {
+            info!(password = "hunter1", "starting");
+            let span = span!(Level::INFO, "login", password = "hunter2");
+            let _enter = span.enter();
+            info!(password = "hunter3", user = "yak", "logging in");
+        },
+    );
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0]["password"], "[REDACTED]");
+    let line = &output[1];
+    assert_eq!(line["password"], "[REDACTED]");
+    assert_eq!(line["user"], "yak");
+}
+
+#[test]
+fn span_fields_are_left_out_without_a_field_recorder() {
+    let output = capture_json(
+        |writer| {
+            tracing_subscriber::registry().with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(AsFormatEvent::new(JsonFormatter::new()))
+                    .with_writer(writer),
+            )
+        },
+        || {
+            let span = span!(Level::INFO, "outer", correlation_id = "abc");
+            let _enter = span.enter();
+            info!(answer = 42, "shaving yaks");
+        },
+    );
+
+    assert_eq!(output.len(), 1);
+    let line = &output[0];
+    assert_eq!(line["span"], "outer");
+    assert_eq!(line["answer"], 42);
+    assert!(line.get("correlation_id").is_none());
+}
+
+#[test]
+fn field_recorder_supports_compat_span_ext() {
+    let subscriber = tracing_subscriber::registry().with(FieldRecorder::new());
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = span!(Level::INFO, "outer", correlation_id = "abc");
+        let _enter = span.enter();
+        let span = span!(Level::INFO, "inner");
+
+        assert_eq!(
+            span.get_stored::<Value>("correlation_id"),
+            Some(Value::from("abc"))
+        );
+    });
+}
+
+#[test]
+fn format_event_can_be_used_by_compat_layer() {
+    let format = tracing_subscriber::fmt::format()
+        .without_time()
+        .with_target(false);
+    let output = run_layer(FromFormatEvent::new(format), identity, || {
+        let span = span!(Level::INFO, "outer", correlation_id = "abc");
+        let _enter = span.enter();
+        info!(answer = 42, "shaving yaks");
+    });
+
+    assert_eq!(
+        output.lines(),
+        [" INFO outer{correlation_id=\"abc\"}: shaving yaks answer=42"]
+    );
+}
@@ -0,0 +1,162 @@
+mod mock_writer;
+
+use std::convert::identity;
+
+use layer::compat_span_ext::CompatSpanExt;
+use layer::fields::FieldOrder;
+use layer::fmt::json::JsonFormatter;
+use layer::fmt::CollisionPolicy;
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+use tracing::{field, info, info_span};
+use tracing_subscriber::Registry;
+
+use crate::mock_writer::run_layer;
+
+fn run_and_get_keys<F: Fn()>(
+    order: FieldOrder,
+    formatter: JsonFormatter<Registry>,
+    action: F,
+) -> Vec<Vec<String>> {
+    let formatter = formatter.without_timestamp();
+    run_layer(formatter, |layer| layer.with_field_order(order), action)
+        .lines()
+        .iter()
+        .map(|line| {
+            let Keys(keys) = serde_json::from_str(line).unwrap();
+            keys
+        })
+        .collect()
+}
+
+// The keys of a line in the order they were written, leaving out the ones every line has.
+struct Keys(Vec<String>);
+
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeysVisitor;
+
+        impl<'de> Visitor<'de> for KeysVisitor {
+            type Value = Keys;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Keys, A::Error> {
+                let mut keys = Vec::new();
+                while let Some((key, IgnoredAny)) = map.next_entry::<String, IgnoredAny>()? {
+                    if !matches!(key.as_str(), "level" | "span") && !key.starts_with("source.") {
+                        keys.push(key);
+                    }
+                }
+                Ok(Keys(keys))
+            }
+        }
+
+        deserializer.deserialize_map(KeysVisitor)
+    }
+}
+
+fn action() {
+    let span = info_span!("request", user = field::Empty, correlation_id = "abc");
+    let _enter = span.enter();
+    span.record("user", "olly");
+    span.set_stored("stored", "value").unwrap();
+    info!(zebra = 1, apple = 2, "working");
+}
+
+#[test]
+fn alphabetical_by_default() {
+    let keys = run_and_get_keys(FieldOrder::default(), JsonFormatter::new(), action);
+
+    assert_eq!(
+        keys[0],
+        [
+            "title",
+            "correlation_id",
+            "stored",
+            "user",
+            "apple",
+            "zebra"
+        ]
+    );
+}
+
+#[test]
+fn declaration_order() {
+    let keys = run_and_get_keys(FieldOrder::Declaration, JsonFormatter::new(), action);
+
+    assert_eq!(
+        keys[0],
+        [
+            "title",
+            "user",
+            "correlation_id",
+            "stored",
+            "zebra",
+            "apple"
+        ]
+    );
+}
+
+#[test]
+fn recorded_order() {
+    let keys = run_and_get_keys(FieldOrder::Recorded, JsonFormatter::new(), action);
+
+    assert_eq!(
+        keys[0],
+        [
+            "title",
+            "correlation_id",
+            "user",
+            "stored",
+            "zebra",
+            "apple"
+        ]
+    );
+}
+
+#[test]
+fn pinned_keys_come_right_after_title() {
+    let formatter = JsonFormatter::new().with_pinned_keys(["correlation_id", "zebra", "missing"]);
+    let keys = run_and_get_keys(FieldOrder::default(), formatter, action);
+
+    assert_eq!(
+        keys[0],
+        [
+            "title",
+            "correlation_id",
+            "zebra",
+            "stored",
+            "user",
+            "apple"
+        ]
+    );
+}
+
+#[test]
+fn pinned_keys_follow_the_collision_policy() {
+    let action = || {
+        let _outer = info_span!("outer", id = "outer").entered();
+        let _inner = info_span!("inner", id = "inner").entered();
+        info!(other = 1, "working");
+    };
+
+    for (policy, pinned, first) in [
+        (CollisionPolicy::InnermostWins, "id", "inner"),
+        (CollisionPolicy::OutermostWins, "id", "outer"),
+        (CollisionPolicy::Prefix, "outer.id", "outer"),
+    ] {
+        let formatter = JsonFormatter::new()
+            .with_collisions(policy)
+            .with_pinned_keys([pinned]);
+        let output = run_layer(formatter, identity, action).text();
+
+        let Keys(keys) = serde_json::from_str(output.trim()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(keys[2], pinned, "{:?}", policy);
+        assert_eq!(keys.iter().filter(|key| *key == pinned).count(), 1);
+        assert_eq!(line[pinned], first, "{:?}", policy);
+    }
+}
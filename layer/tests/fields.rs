@@ -0,0 +1,76 @@
+use layer::compat_layer::Visitor;
+use layer::fields::{FieldMap, FieldStr, FieldValue};
+use serde_json::{json, Value};
+
+#[test]
+fn fields_keep_the_order_they_were_first_recorded_in() {
+    let mut fields = FieldMap::new();
+    fields.insert("zebra", 1u64);
+    fields.insert("apple", "red");
+    fields.insert("mango", true);
+    assert_eq!(fields.insert("zebra", 2u64), Some(FieldValue::U64(1)));
+
+    let keys: Vec<_> = fields.keys().map(|key| key.as_ref()).collect();
+    assert_eq!(keys, ["zebra", "apple", "mango"]);
+    assert_eq!(
+        serde_json::to_string(&fields).unwrap(),
+        r#"{"zebra":2,"apple":"red","mango":true}"#
+    );
+}
+
+#[test]
+fn removing_a_field_keeps_the_order_of_the_rest() {
+    let mut fields = FieldMap::new();
+    for (i, key) in ["a", "b", "c", "d"].into_iter().enumerate() {
+        fields.insert(key, i as u64);
+    }
+    assert_eq!(fields.remove("b"), Some(FieldValue::U64(1)));
+    assert_eq!(fields.remove("b"), None);
+
+    let keys: Vec<_> = fields.keys().map(|key| key.as_ref()).collect();
+    assert_eq!(keys, ["a", "c", "d"]);
+}
+
+#[test]
+fn long_strings_move_to_the_heap() {
+    let mut s = FieldStr::from("short");
+    assert_eq!(s.as_str(), "short");
+
+    let long = "a string that is much too long to be kept inline";
+    s.push_str(" then ");
+    s.push_str(long);
+    assert_eq!(s.as_str(), format!("short then {}", long));
+    assert_eq!(String::from(s), format!("short then {}", long));
+}
+
+#[test]
+fn json_values_convert_to_the_typed_variants() {
+    assert_eq!(FieldValue::from(json!(3)), FieldValue::U64(3));
+    assert_eq!(FieldValue::from(json!(-3)), FieldValue::I64(-3));
+    assert_eq!(FieldValue::from(json!(1.5)), FieldValue::F64(1.5));
+    assert_eq!(FieldValue::from(json!("cat")), FieldValue::from("cat"));
+    assert_eq!(
+        FieldValue::from(json!([1, 2])),
+        FieldValue::Json(json!([1, 2]))
+    );
+
+    for value in [json!(null), json!(-3), json!("cat"), json!({"a": [true]})] {
+        assert_eq!(Value::from(FieldValue::from(value.clone())), value);
+        assert_eq!(
+            FieldValue::from(value.clone()).to_string(),
+            value.to_string()
+        );
+    }
+}
+
+#[test]
+fn visitor_fields_can_be_read_as_json_values() {
+    let mut visitor = Visitor::default();
+    visitor.fields_mut().insert("zebra", 1u64);
+    visitor.fields_mut().insert("apple", "red");
+
+    let fields = visitor.fields_json();
+    assert_eq!(fields.keys().collect::<Vec<_>>(), [&"apple", &"zebra"]);
+    assert_eq!(fields["apple"], json!("red"));
+    assert_eq!(fields["zebra"], json!(1));
+}
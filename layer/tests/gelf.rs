@@ -0,0 +1,286 @@
+mod mock_writer;
+
+use std::collections::BTreeMap;
+use std::convert::identity;
+use std::io::{Read, Write};
+use std::net::{TcpListener, UdpSocket};
+use std::time::{Duration, UNIX_EPOCH};
+
+use flate2::read::ZlibDecoder;
+use layer::compat_layer::CompatLayer;
+use layer::compat_span_ext::CompatSpanExt;
+use layer::fmt::gelf::GelfFormatter;
+use layer::fmt::time::FixedClock;
+use layer::gelf::GelfWriter;
+use serde_json::Value;
+use tracing::{info_span, warn};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+use crate::mock_writer::run_layer;
+
+fn formatter() -> GelfFormatter<Registry> {
+    GelfFormatter::new()
+        .with_host("cat-host")
+        .with_clock(FixedClock(UNIX_EPOCH + Duration::from_millis(1_500)))
+}
+
+fn run<W, F>(make_writer: W, action: F)
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+    F: Fn(),
+{
+    let subscriber =
+        tracing_subscriber::registry().with(CompatLayer::new(formatter(), make_writer));
+    tracing::subscriber::with_default(subscriber, action);
+}
+
+// A stand-in for a Graylog UDP input, reassembles chunked messages and decompresses them.
+struct GraylogUdp {
+    socket: UdpSocket,
+}
+
+impl GraylogUdp {
+    fn bind() -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Self { socket }
+    }
+
+    fn addr(&self) -> std::net::SocketAddr {
+        self.socket.local_addr().unwrap()
+    }
+
+    // Returns the message and the number of datagrams it was sent in.
+    fn receive(&self) -> (Value, usize) {
+        let mut chunks = BTreeMap::new();
+        let mut buf = vec![0; 65_536];
+
+        loop {
+            let len = self.socket.recv(&mut buf).unwrap();
+            let datagram = &buf[..len];
+
+            let payload = match datagram {
+                [0x1e, 0x0f, header @ ..] => {
+                    let (id, rest) = header.split_at(8);
+                    let (sequence, count) = (rest[0], rest[1]);
+                    chunks.insert(sequence, (id.to_vec(), rest[2..].to_vec()));
+                    if chunks.len() < count as usize {
+                        continue;
+                    }
+
+                    let ids: Vec<_> = chunks.values().map(|(id, _)| id).collect();
+                    assert!(ids.windows(2).all(|w| w[0] == w[1]), "chunks were mixed up");
+                    chunks.values().flat_map(|(_, data)| data.clone()).collect()
+                }
+                datagram => datagram.to_vec(),
+            };
+
+            let json = match payload.first() {
+                // zlib header
+                Some(0x78) => {
+                    let mut json = Vec::new();
+                    ZlibDecoder::new(&payload[..])
+                        .read_to_end(&mut json)
+                        .unwrap();
+                    json
+                }
+                _ => payload,
+            };
+
+            return (serde_json::from_slice(&json).unwrap(), chunks.len().max(1));
+        }
+    }
+}
+
+#[test]
+fn messages_have_the_gelf_fields() {
+    let output = run_layer(formatter(), identity, || {
+        let span = info_span!(
+            "get_cat",
+            id = 7,
+            cached = false,
+            tags = tracing::field::Empty
+        );
+        span.set_stored("nothing", ()).unwrap();
+        let _enter = span.entered();
+        warn!(url = "https://cats", "Retrying link\ncaused by: timed out");
+    })
+    .json();
+
+    let message = &output[0];
+    assert_eq!(message["version"], "1.1");
+    assert_eq!(message["host"], "cat-host");
+    assert_eq!(message["short_message"], "Retrying link");
+    assert_eq!(
+        message["full_message"],
+        "Retrying link\ncaused by: timed out"
+    );
+    assert_eq!(message["timestamp"], 1.5);
+    assert_eq!(message["level"], 4);
+    assert_eq!(message["_span"], "get_cat");
+    assert_eq!(message["_target"], "gelf");
+    assert_eq!(message["_url"], "https://cats");
+    assert_eq!(message["__id"], 7);
+    assert_eq!(message["_cached"], "false");
+    assert!(message.get("_id").is_none());
+    assert!(message.get("_nothing").is_none());
+}
+
+#[test]
+fn single_line_messages_have_no_full_message() {
+    let output = run_layer(formatter(), identity, || tracing::info!("hi")).json();
+
+    assert_eq!(output[0]["short_message"], "hi");
+    assert_eq!(output[0]["level"], 6);
+    assert!(output[0].get("full_message").is_none());
+}
+
+#[test]
+fn blank_messages_are_sent_with_the_span_or_event_name() {
+    let output = run_layer(formatter(), identity, || {
+        tracing::info!("");
+        let _enter = info_span!("get_cat").entered();
+        tracing::info!("\nmeow");
+    })
+    .json();
+
+    assert!(output[0]["short_message"]
+        .as_str()
+        .unwrap()
+        .starts_with("event "));
+    assert_eq!(output[1]["short_message"], "get_cat");
+    assert_eq!(output[1]["full_message"], "\nmeow");
+}
+
+#[test]
+fn fields_cannot_replace_the_formatters_own() {
+    let output = run_layer(formatter(), identity, || {
+        let _enter = info_span!("get_cat", span = "spoofed").entered();
+        tracing::info!(target = "spoofed", "hi");
+    })
+    .json();
+
+    assert_eq!(output[0]["_span"], "get_cat");
+    assert_eq!(output[0]["_target"], "gelf");
+}
+
+#[test]
+fn fields_named_like_the_formatters_own_are_prefixed() {
+    let output = run_layer(formatter(), identity, || {
+        let _enter = info_span!("get_cat", span = "spoofed", file = "cats.rs").entered();
+        tracing::info!(line = 7, target = "spoofed", "hi");
+    })
+    .json();
+
+    let message = &output[0];
+    assert_eq!(message["_span"], "get_cat");
+    assert_eq!(message["_fields.span"], "spoofed");
+    assert_eq!(message["_fields.file"], "cats.rs");
+    assert_eq!(message["_fields.line"], 7);
+    assert_eq!(message["_fields.target"], "spoofed");
+}
+
+#[test]
+fn fields_with_the_same_sanitized_name_get_a_suffix() {
+    let output = run_layer(formatter(), identity, || {
+        let span = info_span!("get_cat");
+        span.set_stored("a b", 1).unwrap();
+        span.set_stored("a/b", 2).unwrap();
+        let _enter = span.entered();
+        warn!(id = 3, _id = 4, "hi");
+    })
+    .json();
+
+    let message = &output[0];
+    assert_eq!(message["_a_b"], 1);
+    assert_eq!(message["_a_b_2"], 2);
+    // Fields are sorted by name, so `_id` comes first.
+    assert_eq!(message["__id"], 4);
+    assert_eq!(message["__id_2"], 3);
+}
+
+#[test]
+fn small_messages_are_sent_in_one_datagram() {
+    let graylog = GraylogUdp::bind();
+    run(GelfWriter::udp(graylog.addr()).unwrap(), || {
+        tracing::info!(answer = 42, "hi");
+    });
+
+    let (message, datagrams) = graylog.receive();
+    assert_eq!(message["short_message"], "hi");
+    assert_eq!(message["_answer"], 42);
+    assert_eq!(datagrams, 1);
+}
+
+#[test]
+fn big_messages_are_chunked() {
+    let graylog = GraylogUdp::bind();
+    let writer = GelfWriter::builder()
+        .with_chunk_size(100)
+        .udp(graylog.addr())
+        .unwrap();
+    let text = "meow ".repeat(100);
+    run(writer, || tracing::info!(text, "hi"));
+
+    let (message, datagrams) = graylog.receive();
+    assert_eq!(message["_text"], text.as_str());
+    assert!(datagrams > 5, "{}", datagrams);
+}
+
+#[test]
+fn messages_can_be_compressed() {
+    let graylog = GraylogUdp::bind();
+    let writer = GelfWriter::builder()
+        .with_compression(true)
+        .with_chunk_size(100)
+        .udp(graylog.addr())
+        .unwrap();
+    let text = "meow ".repeat(1_000);
+    run(writer, || tracing::info!(text, "hi"));
+
+    // The repetitive text compresses well below the 5000 bytes it takes uncompressed.
+    let (message, datagrams) = graylog.receive();
+    assert_eq!(message["_text"], text.as_str());
+    assert!(datagrams < 5, "{}", datagrams);
+}
+
+#[test]
+fn messages_needing_too_many_chunks_are_rejected() {
+    let graylog = GraylogUdp::bind();
+    let mut writer = GelfWriter::builder()
+        .with_chunk_size(20)
+        .udp(graylog.addr())
+        .unwrap();
+
+    let error = writer.write(&[b'x'; 8 * 129]).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn tcp_messages_are_null_terminated() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let writer = GelfWriter::tcp(listener.local_addr().unwrap()).unwrap();
+    let (mut connection, _) = listener.accept().unwrap();
+
+    run(writer, || {
+        tracing::info!("one");
+        tracing::info!("two");
+    });
+
+    let mut received = Vec::new();
+    connection.read_to_end(&mut received).unwrap();
+    let messages: Vec<Value> = received
+        .split(|&b| b == 0)
+        .filter(|m| !m.is_empty())
+        .map(|m| serde_json::from_slice(m).unwrap())
+        .collect();
+
+    assert_eq!(received.last(), Some(&0));
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["short_message"], "one");
+    assert_eq!(messages[1]["short_message"], "two");
+}
@@ -0,0 +1,214 @@
+mod mock_writer;
+
+use std::convert::identity;
+use std::fmt;
+use std::time::UNIX_EPOCH;
+
+use layer::fmt::logfmt::LogfmtFormatter;
+use layer::fmt::time::FixedClock;
+use layer::fmt::CollisionPolicy;
+use tracing::{info, info_span};
+
+use crate::mock_writer::run_layer;
+
+// A minimal logfmt parser, enough to check that every line can be read back.
+fn parse(line: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while chars.peek().is_some() {
+        let key: String = chars.by_ref().take_while(|&c| c != '=').collect();
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => match chars.next().unwrap() {
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        'u' => {
+                            let hex: String = chars.by_ref().take(4).collect();
+                            let c = u32::from_str_radix(&hex, 16).unwrap();
+                            value.push(char::from_u32(c).unwrap());
+                        }
+                        c => value.push(c),
+                    },
+                    c => value.push(c),
+                }
+            }
+            assert!(matches!(chars.next(), None | Some(' ')), "{}", line);
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ' ').collect();
+        }
+        pairs.push((key, value));
+    }
+
+    pairs
+}
+
+fn get<'a>(pairs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    pairs
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+#[test]
+fn lines_have_the_same_fields_as_json() {
+    let formatter = LogfmtFormatter::new().with_clock(FixedClock(UNIX_EPOCH));
+    let lines = run_layer(formatter, identity, || {
+        let _enter = info_span!("request", correlation_id = "abc").entered();
+        info!(attempt = 2, ok = true, "fetching cat");
+    })
+    .lines();
+
+    assert_eq!(lines.len(), 1);
+    let pairs = parse(&lines[0]);
+    let keys: Vec<_> = pairs.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(
+        keys,
+        [
+            "timestamp",
+            "level",
+            "title",
+            "span",
+            "source.filename",
+            "source.line",
+            "source.target",
+            "source.pid",
+            "correlation_id",
+            "attempt",
+            "ok",
+        ]
+    );
+
+    assert!(lines[0].starts_with(
+        "timestamp=1970-01-01T00:00:00.000000Z level=INFO title=\"fetching cat\" span=request "
+    ));
+    assert!(lines[0].ends_with(" correlation_id=abc attempt=2 ok=true"));
+    assert_eq!(get(&pairs, "source.target"), Some("logfmt"));
+}
+
+struct RawDebug(&'static str);
+
+impl fmt::Debug for RawDebug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[test]
+fn values_are_quoted_and_escaped() {
+    let cases = [
+        ("plain", "plain"),
+        ("", "\"\""),
+        ("two words", "\"two words\""),
+        ("a=b", "\"a=b\""),
+        ("say \"hi\"", "\"say \\\"hi\\\"\""),
+        ("back\\slash", "back\\slash"),
+        ("back\\slash and space", "\"back\\\\slash and space\""),
+        ("line\nbreak", "\"line\\nbreak\""),
+        ("\u{1b}[31mred\u{1b}[0m", "\"\\u001b[31mred\\u001b[0m\""),
+        ("lossy \u{fffd}", "\"lossy \u{fffd}\""),
+        ("\u{fffd}", "\"\u{fffd}\""),
+    ];
+
+    for (value, expected) in cases {
+        let lines = run_layer(LogfmtFormatter::new().without_timestamp(), identity, || {
+            info!(value = ?RawDebug(value));
+        })
+        .lines();
+        let line = &lines[0];
+
+        assert!(
+            line.ends_with(&format!(" value={}", expected)),
+            "{:?} was written as {}",
+            value,
+            line
+        );
+        assert_eq!(get(&parse(line), "value"), Some(value), "{}", line);
+    }
+}
+
+#[test]
+fn keys_that_would_break_the_line_are_replaced() {
+    use layer::compat_span_ext::CompatSpanExt;
+
+    let lines = run_layer(LogfmtFormatter::new().without_timestamp(), identity, || {
+        let span = info_span!("request");
+        span.set_stored("odd key=\"x\"", 1).unwrap();
+        let _enter = span.entered();
+        info!("hi");
+    })
+    .lines();
+
+    assert!(lines[0].ends_with(" odd_key__x_=1"), "{}", lines[0]);
+}
+
+#[test]
+fn non_string_values_are_written_as_json() {
+    use layer::compat_span_ext::CompatSpanExt;
+
+    let lines = run_layer(LogfmtFormatter::new().without_timestamp(), identity, || {
+        let span = info_span!("request");
+        span.set_stored("tags", ["a", "b c"]).unwrap();
+        span.set_stored("nothing", ()).unwrap();
+        let _enter = span.entered();
+        info!(ratio = 0.5, "hi");
+    })
+    .lines();
+
+    let pairs = parse(&lines[0]);
+    assert_eq!(get(&pairs, "tags"), Some("[\"a\",\"b c\"]"));
+    assert_eq!(get(&pairs, "nothing"), Some("null"));
+    assert_eq!(get(&pairs, "ratio"), Some("0.5"));
+}
+
+#[test]
+fn nest_is_written_with_prefixes() {
+    let formatter = LogfmtFormatter::new()
+        .without_timestamp()
+        .with_collisions(CollisionPolicy::Nest);
+    let lines = run_layer(formatter, identity, || {
+        let _outer = info_span!("outer", id = 1).entered();
+        let _inner = info_span!("inner", id = 2).entered();
+        info!(id = 3, "hi");
+    })
+    .lines();
+
+    let pairs = parse(&lines[0]);
+    assert_eq!(get(&pairs, "outer.id"), Some("1"));
+    assert_eq!(get(&pairs, "inner.id"), Some("2"));
+    assert_eq!(get(&pairs, "id"), Some("3"));
+}
+
+#[test]
+fn fields_named_like_header_keys_are_prefixed() {
+    let lines = run_layer(LogfmtFormatter::new().without_timestamp(), identity, || {
+        let _span = info_span!("get_cat", span = "mine", timestamp = 1).entered();
+        info!(
+            level = "high",
+            title = "Dr",
+            "source.pid" = 7,
+            "Fetching link!"
+        );
+    })
+    .lines();
+
+    let pairs = parse(&lines[0]);
+    let keys: Vec<_> = pairs.iter().map(|(key, _)| key.as_str()).collect();
+    for key in &keys {
+        assert_eq!(keys.iter().filter(|k| *k == key).count(), 1, "{}", lines[0]);
+    }
+    assert_eq!(get(&pairs, "level"), Some("INFO"));
+    assert_eq!(get(&pairs, "fields.level"), Some("high"));
+    assert_eq!(get(&pairs, "title"), Some("Fetching link!"));
+    assert_eq!(get(&pairs, "fields.title"), Some("Dr"));
+    assert_eq!(get(&pairs, "span"), Some("get_cat"));
+    assert_eq!(get(&pairs, "fields.span"), Some("mine"));
+    assert_eq!(get(&pairs, "fields.source.pid"), Some("7"));
+    // Without timestamps there's nothing for `timestamp` to collide with.
+    assert_eq!(get(&pairs, "timestamp"), Some("1"));
+}
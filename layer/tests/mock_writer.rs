@@ -1,6 +1,18 @@
+// Each test crate only uses some of these.
+#![allow(dead_code)]
+
 use std::io;
 use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
 
+use layer::compat_layer::CompatLayer;
+use layer::fmt::Format;
+use serde_json::Value;
+use tracing::{info, info_span};
+use tracing_core::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
 /// Use a vector of bytes behind a Arc<Mutex> as writer in order to inspect the tracing output
 /// for testing purposes.
 /// Stolen directly from the test suite of tracing-subscriber.
@@ -34,3 +46,126 @@ impl io::Write for MockWriter {
         self.buf()?.flush()
     }
 }
+
+/// Makes `MockWriter`s that all write to the same buffer.
+#[derive(Clone, Default)]
+pub struct MakeMockWriter {
+    buf: Arc<Mutex<Vec<u8>>>,
+}
+
+impl<'a> MakeWriter<'a> for MakeMockWriter {
+    type Writer = MockWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        MockWriter::new(self.buf.clone())
+    }
+}
+
+impl MakeMockWriter {
+    /// Everything written so far.
+    pub fn text(&self) -> String {
+        String::from_utf8(self.buf.lock().unwrap().to_vec()).unwrap()
+    }
+}
+
+/// Runs `action` with the subscriber made by `subscriber` as the default, and returns everything
+/// written to the `MakeMockWriter` it was given.
+pub fn capture_bytes<S, F>(subscriber: impl FnOnce(MakeMockWriter) -> S, action: F) -> Vec<u8>
+where
+    S: Subscriber + Send + Sync + 'static,
+    F: FnOnce(),
+{
+    let make_writer = MakeMockWriter::default();
+    tracing::subscriber::with_default(subscriber(make_writer.clone()), action);
+
+    let output = make_writer.buf.lock().unwrap().to_vec();
+    output
+}
+
+/// Like `capture_bytes`, for output that is text.
+pub fn capture<S, F>(subscriber: impl FnOnce(MakeMockWriter) -> S, action: F) -> String
+where
+    S: Subscriber + Send + Sync + 'static,
+    F: FnOnce(),
+{
+    String::from_utf8(capture_bytes(subscriber, action)).unwrap()
+}
+
+/// Like `capture_bytes`, for output with a JSON object on each line.
+pub fn capture_json<S, F>(subscriber: impl FnOnce(MakeMockWriter) -> S, action: F) -> Vec<Value>
+where
+    S: Subscriber + Send + Sync + 'static,
+    F: FnOnce(),
+{
+    capture(subscriber, action)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// A `CompatLayer` that writes to a `MakeMockWriter`.
+pub type TestLayer<F> = CompatLayer<Registry, F, MakeMockWriter>;
+
+/// Runs `action` with a registry and a `CompatLayer` for `formatter`, set up by `configure`, as
+/// the default subscriber, and returns what the layer wrote. Colors are off unless `configure`
+/// turns them on, whether the tests run in a terminal or not.
+pub fn run_layer<F>(
+    formatter: F,
+    configure: impl FnOnce(TestLayer<F>) -> TestLayer<F>,
+    action: impl FnOnce(),
+) -> Output
+where
+    F: Format<Registry> + Send + Sync + 'static,
+{
+    Output(capture_bytes(
+        |writer| {
+            tracing_subscriber::registry().with(configure(
+                CompatLayer::new(formatter, writer).with_ansi(false),
+            ))
+        },
+        action,
+    ))
+}
+
+/// Logs an event with string, number and boolean fields from two nested spans, for tests of
+/// what a formatter makes of a typical event.
+pub fn fetch_link() {
+    let _cat = info_span!("get_cat", correlation_id = "abc").entered();
+    let _link = info_span!("get_cat_link", attempt = 1).entered();
+    info!(
+        url = "https://cats",
+        ratio = 0.5,
+        cached = true,
+        "Fetching link!"
+    );
+}
+
+/// Configures a `TestLayer` to log spans as well as events.
+pub fn with_spans<F: Format<Registry>>(layer: TestLayer<F>) -> TestLayer<F> {
+    layer.with_spans(true)
+}
+
+/// The output of `run_layer`.
+pub struct Output(Vec<u8>);
+
+impl Output {
+    pub fn bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn text(self) -> String {
+        String::from_utf8(self.0).unwrap()
+    }
+
+    pub fn lines(self) -> Vec<String> {
+        self.text().lines().map(String::from).collect()
+    }
+
+    /// Parses each line as a JSON object.
+    pub fn json(self) -> Vec<Value> {
+        self.text()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
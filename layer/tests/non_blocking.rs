@@ -0,0 +1,226 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use layer::compat_layer::CompatLayer;
+use layer::fmt::json::JsonFormatter;
+use layer::fmt::logfmt::LogfmtFormatter;
+use layer::non_blocking::{NonBlocking, OverflowPolicy};
+use serde_json::Value;
+use tracing::dispatcher::{self, Dispatch};
+use tracing_subscriber::layer::SubscriberExt;
+
+// A writer that blocks every write until its gate is opened, so that tests can fill up the queue
+// of a `NonBlocking` writer deterministically.
+#[derive(Clone, Default)]
+struct GatedWriter {
+    buf: Arc<Mutex<Vec<u8>>>,
+    // (open, entered)
+    gate: Arc<(Mutex<(bool, bool)>, Condvar)>,
+}
+
+impl GatedWriter {
+    fn open(&self) {
+        let (lock, cvar) = &*self.gate;
+        lock.lock().unwrap().0 = true;
+        cvar.notify_all();
+    }
+
+    // Wait for the writer thread to be stuck in `write`, at which point the queue is empty.
+    fn wait_until_entered(&self) {
+        let (lock, cvar) = &*self.gate;
+        let _guard = cvar.wait_while(lock.lock().unwrap(), |(_, entered)| !*entered);
+    }
+
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.buf.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+impl io::Write for GatedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (lock, cvar) = &*self.gate;
+        let mut gate = lock.lock().unwrap();
+        gate.1 = true;
+        cvar.notify_all();
+        let _gate = cvar.wait_while(gate, |(open, _)| !*open).unwrap();
+        self.buf.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn overflow(policy: OverflowPolicy) -> (Vec<String>, u64) {
+    let inner = GatedWriter::default();
+    let (mut writer, guard) = NonBlocking::builder()
+        .with_capacity(2)
+        .with_overflow(policy)
+        .with_report_interval(None)
+        .finish(inner.clone());
+
+    writer.write_all(b"0\n").unwrap();
+    inner.wait_until_entered();
+    for line in ["1\n", "2\n", "3\n", "4\n", "5\n"] {
+        writer.write_all(line.as_bytes()).unwrap();
+    }
+
+    inner.open();
+    drop(guard);
+
+    (inner.lines(), writer.dropped_lines())
+}
+
+#[test]
+fn drop_newest_keeps_the_first_lines() {
+    let (lines, dropped) = overflow(OverflowPolicy::DropNewest);
+
+    assert_eq!(lines, ["0", "1", "2"]);
+    assert_eq!(dropped, 3);
+}
+
+#[test]
+fn drop_oldest_keeps_the_last_lines() {
+    let (lines, dropped) = overflow(OverflowPolicy::DropOldest);
+
+    assert_eq!(lines, ["0", "4", "5"]);
+    assert_eq!(dropped, 3);
+}
+
+#[test]
+fn block_keeps_every_line() {
+    let inner = GatedWriter::default();
+    let (writer, guard) = NonBlocking::builder()
+        .with_capacity(1)
+        .with_overflow(OverflowPolicy::Block)
+        .finish(inner.clone());
+
+    let producer = {
+        let mut writer = writer.clone();
+        thread::spawn(move || {
+            for i in 0..5 {
+                writer.write_all(format!("{}\n", i).as_bytes()).unwrap();
+            }
+        })
+    };
+
+    inner.wait_until_entered();
+    thread::sleep(Duration::from_millis(20));
+    inner.open();
+    producer.join().unwrap();
+    drop(guard);
+
+    assert_eq!(inner.lines(), ["0", "1", "2", "3", "4"]);
+    assert_eq!(writer.dropped_lines(), 0);
+}
+
+#[test]
+fn guard_flushes_pending_lines_on_drop() {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let (mut writer, guard) = NonBlocking::new(SharedBuffer(buffer.clone()));
+
+    for i in 0..100 {
+        writer.write_all(format!("{}\n", i).as_bytes()).unwrap();
+    }
+    drop(guard);
+
+    let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+    assert_eq!(output.lines().count(), 100);
+}
+
+#[test]
+fn dropped_lines_are_reported_as_an_event() {
+    let inner = GatedWriter::default();
+    let (writer, guard) = NonBlocking::builder()
+        .with_capacity(2)
+        .with_overflow(OverflowPolicy::DropOldest)
+        .finish(inner.clone());
+
+    // The report goes to the subscriber the writer was registered with, not the default one.
+    let dispatch = Dispatch::new(
+        tracing_subscriber::registry().with(CompatLayer::new(JsonFormatter::new(), writer)),
+    );
+    dispatcher::with_default(&dispatch, || {
+        tracing::info!("0");
+        inner.wait_until_entered();
+        tracing::info!("1");
+        tracing::info!("2");
+        tracing::info!("3");
+    });
+    inner.open();
+    // Well within the default interval, what's left is reported when the guard is dropped.
+    drop(guard);
+
+    let lines: Vec<Value> = inner
+        .lines()
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let titles: Vec<_> = lines.iter().map(|line| line["title"].clone()).collect();
+    assert_eq!(titles[..3], ["0", "2", "3"]);
+    let report = &lines[3];
+    assert_eq!(report["level"], "WARN");
+    assert_eq!(report["source.target"], "layer::non_blocking");
+    assert_eq!(report["dropped_lines"], 1);
+    assert_eq!(lines.len(), 4);
+}
+
+#[test]
+fn dropped_lines_are_reported_in_the_format_of_the_layer() {
+    let inner = GatedWriter::default();
+    let (writer, guard) = NonBlocking::builder()
+        .with_capacity(1)
+        .with_overflow(OverflowPolicy::DropNewest)
+        .finish(inner.clone());
+
+    let dispatch = Dispatch::new(
+        tracing_subscriber::registry().with(CompatLayer::new(LogfmtFormatter::new(), writer)),
+    );
+    dispatcher::with_default(&dispatch, || {
+        tracing::info!("0");
+        inner.wait_until_entered();
+        tracing::info!("1");
+        tracing::info!("2");
+        tracing::info!("3");
+    });
+    inner.open();
+    drop(guard);
+
+    let lines = inner.lines();
+    assert_eq!(lines.len(), 3);
+    let report = &lines[2];
+    assert!(report.contains("level=WARN"), "{}", report);
+    assert!(report.contains("dropped_lines=2"), "{}", report);
+    assert!(!report.starts_with('{'), "{}", report);
+}
+
+#[test]
+fn lines_written_after_shutdown_are_dropped() {
+    let (mut writer, guard) = NonBlocking::builder()
+        .with_report_interval(None)
+        .finish(io::sink());
+
+    drop(guard);
+    writer.write_all(b"0\n").unwrap();
+
+    assert_eq!(writer.dropped_lines(), 1);
+}
+
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
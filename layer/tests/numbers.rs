@@ -0,0 +1,157 @@
+mod mock_writer;
+
+use layer::compat_span_ext::CompatSpanExt;
+use layer::fields::{NonFinite, Numbers};
+use layer::fmt::json::JsonFormatter;
+use serde_json::Value;
+use tracing::{info, info_span};
+
+use crate::mock_writer::run_layer;
+
+fn floats() {
+    info!(
+        nan = f64::NAN,
+        inf = f64::INFINITY,
+        neg_inf = f64::NEG_INFINITY,
+        half = 0.5,
+        "Measured"
+    );
+}
+
+#[test]
+fn wide_integers_are_written_exactly() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_numbers(Numbers::default()),
+        || {
+            info!(
+                small = 7i128,
+                negative = i128::MIN,
+                large = u128::MAX,
+                "Counted"
+            );
+        },
+    )
+    .text();
+
+    assert!(output.contains(r#""small":7"#), "{}", output);
+    assert!(
+        output.contains(&format!(r#""negative":{}"#, i128::MIN)),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains(&format!(r#""large":{}"#, u128::MAX)),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn non_finite_floats_are_null_by_default() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_numbers(Numbers::default()),
+        floats,
+    )
+    .json();
+
+    assert_eq!(output[0]["nan"], Value::Null);
+    assert_eq!(output[0]["inf"], Value::Null);
+    assert_eq!(output[0]["neg_inf"], Value::Null);
+    assert_eq!(output[0]["half"], 0.5);
+}
+
+#[test]
+fn non_finite_floats_as_strings() {
+    let numbers = Numbers {
+        non_finite: NonFinite::String,
+        ..Numbers::default()
+    };
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_numbers(numbers),
+        floats,
+    )
+    .json();
+
+    assert_eq!(output[0]["nan"], "NaN");
+    assert_eq!(output[0]["inf"], "Infinity");
+    assert_eq!(output[0]["neg_inf"], "-Infinity");
+    assert_eq!(output[0]["half"], 0.5);
+}
+
+#[test]
+fn non_finite_floats_dropped() {
+    let numbers = Numbers {
+        non_finite: NonFinite::Drop,
+        ..Numbers::default()
+    };
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_numbers(numbers),
+        || {
+            let _span = info_span!("measuring", ratio = f64::NAN).entered();
+            floats();
+        },
+    )
+    .json();
+
+    for key in ["nan", "inf", "neg_inf", "ratio"] {
+        assert!(output[0].get(key).is_none(), "{} in {}", key, output[0]);
+    }
+    assert_eq!(output[0]["half"], 0.5);
+}
+
+#[test]
+fn integers_outside_the_js_safe_range_are_strings() {
+    let numbers = Numbers {
+        js_safe_integers: true,
+        ..Numbers::default()
+    };
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_numbers(numbers),
+        || {
+            let _span = info_span!("request", request_id = u64::MAX).entered();
+            info!(
+                safe = 9_007_199_254_740_991u64,
+                unsafe_positive = 9_007_199_254_740_992u64,
+                unsafe_negative = -9_007_199_254_740_992i64,
+                wide = u128::MAX,
+                "Counted"
+            );
+        },
+    )
+    .json();
+
+    assert_eq!(output[0]["safe"], 9_007_199_254_740_991u64);
+    assert_eq!(output[0]["unsafe_positive"], "9007199254740992");
+    assert_eq!(output[0]["unsafe_negative"], "-9007199254740992");
+    assert_eq!(output[0]["wide"], u128::MAX.to_string());
+    assert_eq!(output[0]["request_id"], u64::MAX.to_string());
+}
+
+#[test]
+fn stored_integers_outside_the_js_safe_range_are_strings() {
+    let numbers = Numbers {
+        js_safe_integers: true,
+        ..Numbers::default()
+    };
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_numbers(numbers),
+        || {
+            let span = info_span!("request");
+            span.set_stored("ids", [1, u64::MAX]).unwrap();
+            let _enter = span.enter();
+            info!("Counted");
+        },
+    )
+    .json();
+
+    assert_eq!(
+        output[0]["ids"],
+        serde_json::json!([1, u64::MAX.to_string()])
+    );
+}
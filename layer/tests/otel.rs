@@ -0,0 +1,82 @@
+#![cfg(feature = "opentelemetry")]
+
+mod mock_writer;
+
+use layer::compat_layer::CompatLayer;
+use layer::fmt::json::JsonFormatter;
+use opentelemetry::sdk::trace::TracerProvider;
+use opentelemetry::trace::TracerProvider as _;
+use serde_json::Value;
+use tracing::{info, span, Level};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+use crate::mock_writer::capture_json;
+
+fn run_with_formatter<F: Fn()>(formatter: JsonFormatter<Registry>, action: F) -> Vec<Value> {
+    let provider = TracerProvider::builder().build();
+    capture_json(
+        |writer| {
+            tracing_subscriber::registry()
+                .with(CompatLayer::new(formatter, writer).with_spans(true))
+                .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+        },
+        action,
+    )
+}
+
+fn is_hex(value: &Value, len: usize) -> bool {
+    value
+        .as_str()
+        .map(|s| s.len() == len && s.chars().all(|c| c.is_ascii_hexdigit()))
+        .unwrap_or(false)
+}
+
+#[test]
+fn events_carry_trace_and_span_ids() {
+    let output = run_with_formatter(JsonFormatter::new(), || {
+        let outer = span!(Level::INFO, "outer");
+        let _enter = outer.enter();
+        info!("in outer");
+        let inner = span!(Level::INFO, "inner");
+        let _enter = inner.enter();
+        info!("in inner");
+    });
+
+    // start outer, in outer, start inner, in inner, end inner, end outer
+    assert_eq!(output.len(), 6);
+    for line in &output {
+        assert!(is_hex(&line["trace_id"], 32), "{}", line);
+        assert!(is_hex(&line["span_id"], 16), "{}", line);
+        assert_eq!(line["trace_id"], output[0]["trace_id"]);
+    }
+
+    let outer_span_id = &output[0]["span_id"];
+    let inner_span_id = &output[2]["span_id"];
+    assert_ne!(outer_span_id, inner_span_id);
+    assert_eq!(&output[1]["span_id"], outer_span_id);
+    assert_eq!(&output[3]["span_id"], inner_span_id);
+    assert_eq!(&output[4]["span_id"], inner_span_id);
+    assert_eq!(&output[5]["span_id"], outer_span_id);
+}
+
+#[test]
+fn events_outside_of_spans_have_no_ids() {
+    let output = run_with_formatter(JsonFormatter::new(), || info!("no span"));
+
+    assert!(output[0].get("trace_id").is_none());
+    assert!(output[0].get("span_id").is_none());
+}
+
+#[test]
+fn otel_span_id_moves_aside_for_span_ids() {
+    let output = run_with_formatter(JsonFormatter::new().with_span_ids(true), || {
+        let _span = span!(Level::INFO, "outer").entered();
+        info!("in outer");
+    });
+
+    assert!(is_hex(&output[1]["trace_id"], 32), "{}", output[1]);
+    assert!(is_hex(&output[1]["otel.span_id"], 16), "{}", output[1]);
+    assert!(is_hex(&output[1]["span_id"], 16), "{}", output[1]);
+    assert_ne!(output[1]["span_id"], output[1]["otel.span_id"]);
+}
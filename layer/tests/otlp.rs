@@ -0,0 +1,146 @@
+mod mock_writer;
+
+use std::convert::identity;
+use std::time::{Duration, UNIX_EPOCH};
+
+use layer::fmt::otlp::OtlpFormatter;
+use layer::fmt::time::FixedClock;
+use layer::fmt::CollisionPolicy;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::mock_writer::{fetch_link, run_layer};
+
+// The single log record of an export request.
+fn log_record(request: &Value) -> &Value {
+    let resource_logs = request["resourceLogs"].as_array().unwrap();
+    assert_eq!(resource_logs.len(), 1);
+    let scope_logs = resource_logs[0]["scopeLogs"].as_array().unwrap();
+    assert_eq!(scope_logs.len(), 1);
+    let records = scope_logs[0]["logRecords"].as_array().unwrap();
+    assert_eq!(records.len(), 1);
+    &records[0]
+}
+
+fn attribute<'a>(attributes: &'a Value, key: &str) -> Option<&'a Value> {
+    attributes
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|attribute| attribute["key"] == key)
+        .map(|attribute| &attribute["value"])
+}
+
+#[test]
+fn each_event_is_an_export_request_with_one_log_record() {
+    let time = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+    let formatter = OtlpFormatter::new("cats").with_clock(FixedClock(time));
+    let output = run_layer(formatter, identity, fetch_link).json();
+    assert_eq!(output.len(), 1);
+
+    let request = &output[0];
+    assert_eq!(
+        request["resourceLogs"][0]["scopeLogs"][0]["scope"]["name"],
+        "otlp::mock_writer"
+    );
+
+    let record = log_record(request);
+    assert_eq!(record["timeUnixNano"], "1700000000123456789");
+    assert_eq!(record["observedTimeUnixNano"], "1700000000123456789");
+    assert_eq!(record["severityNumber"], 9);
+    assert_eq!(record["severityText"], "INFO");
+    assert_eq!(record["body"], json!({ "stringValue": "Fetching link!" }));
+
+    let attributes = &record["attributes"];
+    assert_eq!(
+        attribute(attributes, "code.filepath"),
+        Some(&json!({ "stringValue": file!().replace("otlp.rs", "mock_writer.rs") }))
+    );
+    assert!(attribute(attributes, "code.lineno").is_some());
+    assert_eq!(
+        attribute(attributes, "correlation_id"),
+        Some(&json!({ "stringValue": "abc" }))
+    );
+    assert_eq!(
+        attribute(attributes, "attempt"),
+        Some(&json!({ "intValue": "1" }))
+    );
+    assert_eq!(
+        attribute(attributes, "url"),
+        Some(&json!({ "stringValue": "https://cats" }))
+    );
+}
+
+#[test]
+fn fields_have_otlp_value_types() {
+    let output = run_layer(OtlpFormatter::new("cats"), identity, || {
+        warn!(
+            count = -3i64,
+            big = u64::MAX,
+            ratio = 0.5,
+            cached = true,
+            name = "whiskers",
+            "Typed"
+        );
+    })
+    .json();
+    let record = log_record(&output[0]);
+    assert_eq!(record["severityNumber"], 13);
+    assert_eq!(record["severityText"], "WARN");
+
+    let attributes = &record["attributes"];
+    assert_eq!(
+        attribute(attributes, "count"),
+        Some(&json!({ "intValue": "-3" }))
+    );
+    assert_eq!(
+        attribute(attributes, "big"),
+        Some(&json!({ "stringValue": u64::MAX.to_string() }))
+    );
+    assert_eq!(
+        attribute(attributes, "ratio"),
+        Some(&json!({ "doubleValue": 0.5 }))
+    );
+    assert_eq!(
+        attribute(attributes, "cached"),
+        Some(&json!({ "boolValue": true }))
+    );
+    assert_eq!(
+        attribute(attributes, "name"),
+        Some(&json!({ "stringValue": "whiskers" }))
+    );
+}
+
+#[test]
+fn resource_describes_the_service() {
+    let formatter = OtlpFormatter::new("cats")
+        .with_resource_attribute("deployment.environment", "staging")
+        .with_resource_attribute("service.name", "dogs");
+    let output = run_layer(formatter, identity, fetch_link).json();
+    let attributes = &output[0]["resourceLogs"][0]["resource"]["attributes"];
+
+    assert_eq!(
+        attribute(attributes, "service.name"),
+        Some(&json!({ "stringValue": "dogs" }))
+    );
+    assert_eq!(
+        attribute(attributes, "deployment.environment"),
+        Some(&json!({ "stringValue": "staging" }))
+    );
+    assert_eq!(
+        attribute(attributes, "process.pid"),
+        Some(&json!({ "intValue": std::process::id().to_string() }))
+    );
+    assert!(attribute(attributes, "host.name").is_some());
+}
+
+#[test]
+fn nested_collisions_are_prefixed() {
+    let formatter = OtlpFormatter::new("cats").with_collisions(CollisionPolicy::Nest);
+    let output = run_layer(formatter, identity, fetch_link).json();
+    let attributes = &log_record(&output[0])["attributes"];
+
+    assert!(attribute(attributes, "get_cat.correlation_id").is_some());
+    assert!(attribute(attributes, "get_cat_link.attempt").is_some());
+    assert!(attribute(attributes, "url").is_some());
+}
@@ -0,0 +1,161 @@
+mod mock_writer;
+
+use std::time::UNIX_EPOCH;
+
+use layer::fmt::event_format::AsFormatEvent;
+use layer::fmt::pretty::PrettyFormatter;
+use layer::fmt::time::FixedClock;
+use layer::span_events::SpanEvents;
+use tracing::info_span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+use crate::mock_writer::{capture, fetch_link, run_layer, TestLayer};
+
+fn with_end_events(
+    layer: TestLayer<PrettyFormatter<Registry>>,
+) -> TestLayer<PrettyFormatter<Registry>> {
+    layer.with_span_events(SpanEvents::CLOSE)
+}
+
+#[test]
+fn lines_show_the_level_timestamp_span_path_message_and_fields() {
+    let formatter = PrettyFormatter::new().with_clock(FixedClock(UNIX_EPOCH));
+    let lines = run_layer(formatter, with_end_events, fetch_link).lines();
+
+    assert_eq!(
+        lines[0],
+        "1970-01-01T00:00:00.000000Z  INFO get_cat > get_cat_link: Fetching link! \
+         correlation_id=abc attempt=1 cached=true ratio=0.5 url=https://cats"
+    );
+}
+
+#[test]
+fn levels_are_aligned() {
+    let lines = run_layer(
+        PrettyFormatter::new().without_timestamp(),
+        with_end_events,
+        || {
+            tracing::error!("a");
+            tracing::info!("b");
+        },
+    )
+    .lines();
+
+    assert_eq!(lines, ["ERROR a", " INFO b"]);
+}
+
+#[test]
+fn timings_of_end_events_come_first() {
+    let lines = run_layer(
+        PrettyFormatter::new().without_timestamp(),
+        with_end_events,
+        || {
+            info_span!("get_cat", correlation_id = "abc").in_scope(|| {});
+        },
+    )
+    .lines();
+
+    assert_eq!(lines.len(), 1);
+    let line = &lines[0];
+    assert!(line.starts_with(" INFO get_cat: end elapsed="), "{}", line);
+    assert!(line.ends_with(" correlation_id=abc"), "{}", line);
+    let busy = line.find(" busy=").unwrap();
+    let idle = line.find(" idle=").unwrap();
+    assert!(busy < idle && idle < line.find(" correlation_id").unwrap());
+}
+
+#[test]
+fn only_end_events_have_timings() {
+    let formatter = PrettyFormatter::new().without_timestamp().with_ansi(true);
+    let lines = run_layer(formatter, with_end_events, || {
+        tracing::info!(elapsed = "5s", "Waited");
+    })
+    .lines();
+
+    assert_eq!(
+        lines,
+        ["\x1b[32m INFO\x1b[0m Waited \x1b[3melapsed=\x1b[0m5s"]
+    );
+}
+
+#[test]
+fn span_fields_named_like_timings_are_prefixed() {
+    let lines = run_layer(
+        PrettyFormatter::new().without_timestamp(),
+        with_end_events,
+        || {
+            info_span!("get_cat", elapsed = "forever").in_scope(|| {});
+        },
+    )
+    .lines();
+
+    let line = &lines[0];
+    assert_eq!(line.matches(" elapsed=").count(), 1, "{}", line);
+    assert!(line.ends_with(" fields.elapsed=forever"), "{}", line);
+}
+
+#[test]
+fn ansi_colors_can_be_turned_on() {
+    let formatter = PrettyFormatter::new().without_timestamp().with_ansi(true);
+    let lines = run_layer(formatter, with_end_events, fetch_link).lines();
+
+    assert_eq!(
+        lines[0],
+        "\x1b[32m INFO\x1b[0m \x1b[1mget_cat > get_cat_link\x1b[0m: Fetching link! \
+         \x1b[3mcorrelation_id=\x1b[0mabc \x1b[3mattempt=\x1b[0m1 \x1b[3mcached=\x1b[0mtrue \
+         \x1b[3mratio=\x1b[0m0.5 \x1b[3murl=\x1b[0mhttps://cats"
+    );
+
+    let lines = run_layer(
+        PrettyFormatter::new().without_timestamp().with_ansi(true),
+        with_end_events,
+        || info_span!("get_cat").in_scope(|| {}),
+    )
+    .lines();
+    assert!(lines[0].contains(" \x1b[1;36melapsed="), "{}", lines[0]);
+}
+
+#[test]
+fn no_colors_when_not_writing_to_a_terminal() {
+    // A file is never a terminal, even if the layer colors the output.
+    let file = std::fs::File::open("Cargo.toml").unwrap();
+    let formatter = PrettyFormatter::new().with_ansi_for(&file);
+    let lines = run_layer(formatter, |layer| layer.with_ansi(true), fetch_link).lines();
+
+    assert!(!lines[0].contains('\x1b'));
+}
+
+#[test]
+fn colors_follow_the_compat_layer_unless_overridden() {
+    let format = |formatter: PrettyFormatter<Registry>, ansi: bool| {
+        run_layer(formatter, |layer| layer.with_ansi(ansi), fetch_link).text()
+    };
+
+    assert!(format(PrettyFormatter::new(), true).contains('\x1b'));
+    assert!(!format(PrettyFormatter::new(), false).contains('\x1b'));
+    assert!(!format(PrettyFormatter::new().with_ansi(false), true).contains('\x1b'));
+    assert!(format(PrettyFormatter::new().with_ansi(true), false).contains('\x1b'));
+}
+
+#[test]
+fn colors_follow_the_fmt_layer_unless_overridden() {
+    let format = |formatter: PrettyFormatter<Registry>, ansi: bool| {
+        capture(
+            |writer| {
+                tracing_subscriber::registry().with(
+                    tracing_subscriber::fmt::layer()
+                        .with_ansi(ansi)
+                        .with_writer(writer)
+                        .event_format(AsFormatEvent::new(formatter)),
+                )
+            },
+            fetch_link,
+        )
+    };
+
+    assert!(format(PrettyFormatter::new(), true).contains('\x1b'));
+    assert!(!format(PrettyFormatter::new(), false).contains('\x1b'));
+    assert!(!format(PrettyFormatter::new().with_ansi(false), true).contains('\x1b'));
+    assert!(format(PrettyFormatter::new().with_ansi(true), false).contains('\x1b'));
+}
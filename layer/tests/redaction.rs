@@ -0,0 +1,214 @@
+mod mock_writer;
+
+use layer::compat_layer::CompatLayer;
+use layer::compat_span_ext::CompatSpanExt;
+use layer::fmt::json::JsonFormatter;
+use layer::recorder::FieldRecorder;
+use layer::redaction::{Action, Redaction};
+use regex::Regex;
+use serde_json::Value;
+use tracing::{info, span, Level};
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::mock_writer::{capture_json, run_layer, MakeMockWriter};
+
+#[test]
+fn span_fields_are_redacted_before_being_stored() {
+    let redaction = Redaction::new()
+        .name("password", Action::Drop)
+        .glob("auth*", Action::redact());
+
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_spans(true).with_redaction(redaction),
+        || {
+            let span = span!(
+                Level::INFO,
+                "login",
+                password = "hunter2",
+                authorization = "Bearer abc",
+                user = "olly"
+            );
+            let _enter = span.enter();
+            assert_eq!(span.get_stored::<Value>("password"), None);
+            info!("logged in");
+        },
+    )
+    .json();
+
+    assert_eq!(output.len(), 3);
+    for line in output {
+        assert!(line.get("password").is_none());
+        assert_eq!(line["authorization"], "[REDACTED]");
+        assert_eq!(line["user"], "olly");
+    }
+}
+
+#[test]
+fn recorded_and_event_fields_are_redacted() {
+    let redaction = Redaction::new().name("token", Action::redact());
+
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_spans(true).with_redaction(redaction),
+        || {
+            let span = span!(Level::INFO, "request", token = tracing::field::Empty);
+            span.record("token", "abc");
+            let _enter = span.enter();
+            info!(token = "def", "event");
+        },
+    )
+    .json();
+
+    for line in output {
+        assert_eq!(line["token"], "[REDACTED]");
+    }
+}
+
+#[test]
+fn stored_values_are_redacted() {
+    let redaction = Redaction::new()
+        .name("password", Action::Drop)
+        .glob("*token*", Action::redact());
+
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_spans(true).with_redaction(redaction),
+        || {
+            let span = span!(Level::INFO, "request");
+            span.set_stored("password", "hunter2").unwrap();
+            span.set_stored("api_token", "abc").unwrap();
+            span.set_stored("user", "olly").unwrap();
+            assert_eq!(span.get_stored::<Value>("password"), None);
+            assert_eq!(span.get_stored::<Value>("api_token").unwrap(), "[REDACTED]");
+            let _enter = span.enter();
+            info!("event");
+        },
+    )
+    .json();
+
+    for line in output {
+        assert!(line.get("password").is_none(), "{}", line);
+        assert_eq!(line["api_token"], "[REDACTED]");
+        assert_eq!(line["user"], "olly");
+    }
+}
+
+#[test]
+fn nested_keys_are_redacted() {
+    let redaction = Redaction::new()
+        .name("password", Action::Drop)
+        .glob("*token*", Action::redact());
+
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_spans(true).with_redaction(redaction),
+        || {
+            let span = span!(Level::INFO, "request");
+            let login = serde_json::json!({
+                "user": { "name": "olly", "password": "hunter2" },
+                "sessions": [{ "api_token": { "id": 1 } }],
+            });
+            span.set_stored("login", login).unwrap();
+            let _enter = span.enter();
+            info!("event");
+        },
+    )
+    .json();
+
+    assert_eq!(
+        output[0]["login"],
+        serde_json::json!({
+            "user": { "name": "olly" },
+            "sessions": [{ "api_token": "[REDACTED]" }],
+        })
+    );
+}
+
+#[test]
+fn values_are_hashed_consistently() {
+    let email = Regex::new(r"[\w.+-]+@[\w-]+\.[\w.]+").unwrap();
+    let redaction = Redaction::new().value(email, Action::hash(b"key"));
+
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_spans(true).with_redaction(redaction),
+        || {
+            let span = span!(Level::INFO, "signup", email = "olly@example.com");
+            let _enter = span.enter();
+            info!("welcome olly@example.com");
+            info!(other = "olly@example.com", "done");
+        },
+    )
+    .json();
+
+    let hash = output[0]["email"].as_str().unwrap().to_owned();
+    assert_eq!(hash.len(), 64);
+    assert!(!hash.contains("olly"));
+    assert_eq!(output[1]["title"], format!("welcome {}", hash));
+    assert_eq!(output[2]["other"], hash.as_str());
+}
+
+#[test]
+fn field_recorder_redacts_span_fields_it_records_first() {
+    let email = Regex::new(r"[\w.+-]+@[\w-]+\.[\w.]+").unwrap();
+    let redaction = Redaction::new()
+        .name("password", Action::Drop)
+        .value(email, Action::hash(b"key"));
+    let output = capture_json(
+        |writer| {
+            tracing_subscriber::registry()
+                .with(FieldRecorder::new().with_redaction(redaction.clone()))
+                .with(
+                    CompatLayer::new(JsonFormatter::new(), writer)
+                        .with_redaction(redaction.clone()),
+                )
+        },
+        || {
+            let span = span!(
+                Level::INFO,
+                "signup",
+                password = "hunter2",
+                email = "olly@example.com"
+            );
+            let _enter = span.enter();
+            info!("welcome");
+        },
+    );
+    let line = &output[0];
+    assert!(line.get("password").is_none(), "{}", line);
+
+    // Hashed once, by the recorder, and not again by the `CompatLayer`.
+    let alone = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_spans(true).with_redaction(redaction),
+        || {
+            let _enter = span!(Level::INFO, "signup", email = "olly@example.com").entered();
+            info!("welcome");
+        },
+    )
+    .json();
+    assert_eq!(line["email"], alone[0]["email"]);
+}
+
+#[test]
+fn span_fields_are_only_recorded_by_the_first_layer() {
+    let redaction = Redaction::new().name("token", Action::redact());
+    let first = MakeMockWriter::default();
+    let second = MakeMockWriter::default();
+    let subscriber = tracing_subscriber::registry()
+        .with(CompatLayer::new(JsonFormatter::new(), first.clone()).with_redaction(redaction))
+        .with(CompatLayer::new(JsonFormatter::new(), second.clone()));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = span!(Level::INFO, "request", token = tracing::field::Empty);
+        span.record("token", "abc");
+        let _enter = span.enter();
+        info!("event");
+    });
+
+    for writer in [first, second] {
+        let line: Value = serde_json::from_str(&writer.text()).unwrap();
+        assert_eq!(line["token"], "[REDACTED]", "{}", line);
+    }
+}
@@ -0,0 +1,253 @@
+mod mock_writer;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use layer::compat_layer::CompatLayer;
+use layer::fmt::json::JsonFormatter;
+use layer::slow_spans::SpanThresholds;
+use serde_json::Value;
+use tracing::span::{Attributes, Id};
+use tracing::{info_span, warn_span, Event, Level, Metadata, Span, Subscriber};
+use tracing_core::callsite::Identifier;
+use tracing_core::subscriber::Interest;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+use crate::mock_writer::{capture, run_layer, MakeMockWriter};
+
+// Thresholds are either zero or an hour, so which spans are reported doesn't depend on how fast the
+// test happens to run.
+const NEVER: Duration = Duration::from_secs(3600);
+
+// A span that was entered for a millisecond is always past a threshold of zero.
+fn tick_in(span: Span) {
+    span.in_scope(|| thread::sleep(Duration::from_millis(1)));
+}
+
+fn wait_for(writer: &MakeMockWriter, text: &str) {
+    let start = Instant::now();
+    while !writer.text().contains(text) {
+        assert!(start.elapsed() < Duration::from_secs(10), "no {:?}", text);
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn thresholds_match_by_name_then_target_then_fallback() {
+    let thresholds = SpanThresholds::new()
+        .name("get_cat", Duration::from_secs(1))
+        .target("demo::db", Duration::from_secs(2));
+
+    let threshold =
+        |thresholds: &SpanThresholds, span: Span| thresholds.threshold(span.metadata().unwrap());
+
+    assert_eq!(
+        threshold(&thresholds, info_span!(target: "demo::db", "get_cat")),
+        Some(Duration::from_secs(1))
+    );
+    assert_eq!(
+        threshold(&thresholds, info_span!(target: "demo::db::pool", "query")),
+        Some(Duration::from_secs(2))
+    );
+    assert_eq!(
+        threshold(&thresholds, info_span!(target: "demo::dbx", "query")),
+        None
+    );
+
+    let thresholds = thresholds.fallback(Duration::from_secs(3));
+    assert_eq!(
+        threshold(&thresholds, info_span!(target: "demo::dbx", "query")),
+        Some(Duration::from_secs(3))
+    );
+}
+
+#[test]
+fn spans_over_their_threshold_are_reported_when_they_close() {
+    let thresholds = SpanThresholds::new()
+        .name("slow", Duration::ZERO)
+        .fallback(NEVER);
+
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_slow_spans(thresholds),
+        || {
+            let request = info_span!("request", correlation_id = "abc");
+            let _enter = request.enter();
+            tick_in(info_span!("slow", attempt = 1));
+            tick_in(info_span!("fast"));
+        },
+    )
+    .json();
+
+    assert_eq!(output.len(), 1, "{:?}", output);
+    let warning = &output[0];
+    assert_eq!(warning["level"], Level::WARN.as_str());
+    assert_eq!(warning["title"], "slow span");
+    assert_eq!(warning["span"], "slow");
+    assert_eq!(warning["attempt"], 1);
+    assert_eq!(warning["correlation_id"], "abc");
+    assert_eq!(warning["threshold_ms"], 0.0);
+    assert!(warning["elapsed_ms"].as_f64().unwrap() >= 1.0);
+}
+
+#[test]
+fn spans_open_past_their_deadline_are_reported_before_they_close() {
+    let deadlines = SpanThresholds::new().name("hung", Duration::ZERO);
+
+    let writer = MakeMockWriter::default();
+    let subscriber = tracing_subscriber::registry().with(
+        CompatLayer::new(JsonFormatter::new(), writer.clone())
+            .with_hung_spans(deadlines)
+            .with_spans(true),
+    );
+    tracing::subscriber::with_default(subscriber, || {
+        let request = info_span!("request", correlation_id = "abc");
+        let _enter = request.enter();
+        // The span is past its deadline right away, it only closes once that was reported.
+        let hung = info_span!("hung");
+        wait_for(&writer, "hung span");
+        hung.in_scope(|| {});
+    });
+
+    let output: Vec<Value> = writer
+        .text()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let titles: Vec<_> = output.iter().map(|line| &line["title"]).collect();
+    assert_eq!(
+        titles,
+        ["start", "hung span", "start", "end", "end"],
+        "{:?}",
+        output
+    );
+
+    let warning = &output[1];
+    assert_eq!(warning["level"], Level::WARN.as_str());
+    assert_eq!(warning["span"], "hung");
+    assert_eq!(warning["correlation_id"], "abc");
+    assert_eq!(warning["threshold_ms"], 0.0);
+}
+
+#[test]
+fn warnings_go_through_the_whole_subscriber() {
+    let thresholds = SpanThresholds::new().name("slow", Duration::ZERO);
+
+    // Other layers see the warning too.
+    let output = capture(
+        |writer: MakeMockWriter| {
+            tracing_subscriber::registry()
+                .with(
+                    CompatLayer::new(JsonFormatter::new(), writer.clone())
+                        .with_slow_spans(thresholds.clone()),
+                )
+                .with(tracing_subscriber::fmt::layer().with_writer(writer))
+        },
+        || tick_in(info_span!("slow")),
+    );
+    assert_eq!(output.matches("slow span").count(), 2, "{}", output);
+
+    // And it's filtered like any other event.
+    let output = capture(
+        |writer| {
+            tracing_subscriber::registry()
+                .with(LevelFilter::ERROR)
+                .with(CompatLayer::new(JsonFormatter::new(), writer).with_slow_spans(thresholds))
+        },
+        || tick_in(info_span!("slow")),
+    );
+    assert_eq!(output, "");
+}
+
+#[derive(Clone, Default)]
+struct Kinds(Arc<Mutex<Vec<(bool, Level)>>>);
+
+impl<S: Subscriber> Layer<S> for Kinds {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        self.0
+            .lock()
+            .unwrap()
+            .push((meta.is_event(), *meta.level()));
+    }
+}
+
+#[test]
+fn warnings_have_event_metadata_even_for_warn_spans() {
+    let thresholds = SpanThresholds::new().fallback(Duration::ZERO);
+    let kinds = Kinds::default();
+
+    capture(
+        |writer| {
+            tracing_subscriber::registry()
+                .with(CompatLayer::new(JsonFormatter::new(), writer).with_slow_spans(thresholds))
+                .with(kinds.clone())
+        },
+        || {
+            tick_in(info_span!("info"));
+            tick_in(warn_span!("warn"));
+        },
+    );
+
+    assert_eq!(
+        *kinds.0.lock().unwrap(),
+        [(true, Level::WARN), (true, Level::WARN)]
+    );
+}
+
+// Keeps the callsites of spans and events, and of every callsite registered.
+#[derive(Clone, Default)]
+struct Callsites {
+    spans: Arc<Mutex<Vec<Identifier>>>,
+    events: Arc<Mutex<Vec<Identifier>>>,
+    registered: Arc<Mutex<Vec<Identifier>>>,
+}
+
+impl<S: Subscriber> Layer<S> for Callsites {
+    fn register_callsite(&self, meta: &'static Metadata<'static>) -> Interest {
+        self.registered.lock().unwrap().push(meta.callsite());
+        Interest::always()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+        self.spans.lock().unwrap().push(attrs.metadata().callsite());
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(event.metadata().callsite());
+    }
+}
+
+#[test]
+fn warnings_have_a_registered_callsite_of_their_own() {
+    let thresholds = SpanThresholds::new().fallback(Duration::ZERO);
+    let callsites = Callsites::default();
+
+    capture(
+        |writer| {
+            tracing_subscriber::registry()
+                .with(CompatLayer::new(JsonFormatter::new(), writer).with_slow_spans(thresholds))
+                .with(callsites.clone())
+        },
+        || {
+            tick_in(info_span!("info"));
+            tick_in(warn_span!("warn"));
+        },
+    );
+
+    let spans = callsites.spans.lock().unwrap();
+    let registered = callsites.registered.lock().unwrap();
+    let events = callsites.events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_ne!(events[0], events[1]);
+    for callsite in events.iter() {
+        assert!(!spans.contains(callsite));
+        assert!(registered.contains(callsite));
+    }
+}
@@ -0,0 +1,222 @@
+mod mock_writer;
+
+use std::thread;
+use std::time::Duration;
+
+use layer::compat_layer::CompatLayer;
+use layer::fmt::json::JsonFormatter;
+use layer::span_events::{DurationFields, SpanEvents, SpanMessages};
+use serde_json::Value;
+use tracing::{info, span, Level};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::mock_writer::{capture_json, run_layer};
+
+fn messages(output: &[Value]) -> Vec<&str> {
+    output
+        .iter()
+        .map(|line| line["title"].as_str().unwrap())
+        .collect()
+}
+
+fn enter_twice() {
+    let span = span!(Level::INFO, "work", id = 1);
+    span.in_scope(|| info!("first"));
+    span.in_scope(|| info!("second"));
+}
+
+#[test]
+fn with_spans_logs_the_first_enter_and_close() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_spans(true),
+        enter_twice,
+    )
+    .json();
+
+    assert_eq!(messages(&output), ["start", "first", "second", "end"]);
+    assert_eq!(output[0]["span"], "work");
+    assert_eq!(output[0]["id"], 1);
+}
+
+#[test]
+fn full_logs_every_lifecycle_event() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_span_events(SpanEvents::FULL),
+        enter_twice,
+    )
+    .json();
+
+    assert_eq!(
+        messages(&output),
+        ["new", "enter", "first", "exit", "enter", "second", "exit", "end"]
+    );
+}
+
+#[test]
+fn first_enter_is_only_logged_once() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_span_events(SpanEvents::FIRST_ENTER | SpanEvents::EXIT),
+        enter_twice,
+    )
+    .json();
+
+    assert_eq!(
+        messages(&output),
+        ["start", "first", "exit", "second", "exit"]
+    );
+}
+
+#[test]
+fn no_span_events_by_default() {
+    let output = run_layer(JsonFormatter::new(), |layer| layer, enter_twice).json();
+
+    assert_eq!(messages(&output), ["first", "second"]);
+}
+
+#[test]
+fn span_events_use_the_level_of_their_span() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_span_events(SpanEvents::NEW | SpanEvents::CLOSE),
+        || {
+            span!(Level::DEBUG, "debug");
+        },
+    )
+    .json();
+
+    assert_eq!(output.len(), 2);
+    for line in output {
+        assert_eq!(line["level"], "DEBUG");
+    }
+}
+
+#[test]
+fn span_event_level_can_be_overridden() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| {
+            layer
+                .with_span_events(SpanEvents::FULL)
+                .with_span_event_level(Level::TRACE)
+        },
+        enter_twice,
+    )
+    .json();
+
+    for line in output {
+        let expected = match line["title"].as_str().unwrap() {
+            "first" | "second" => "INFO",
+            _ => "TRACE",
+        };
+        assert_eq!(line["level"], expected, "{}", line);
+        assert_eq!(line["span"], "work");
+    }
+}
+
+#[test]
+fn span_events_below_the_filter_are_not_logged() {
+    let output = capture_json(
+        |writer| {
+            tracing_subscriber::registry().with(LevelFilter::INFO).with(
+                CompatLayer::new(JsonFormatter::new(), writer)
+                    .with_span_events(SpanEvents::FULL)
+                    .with_span_event_level(Level::TRACE),
+            )
+        },
+        enter_twice,
+    );
+
+    assert_eq!(messages(&output), ["first", "second"]);
+}
+
+#[test]
+fn span_messages_can_be_customised() {
+    let span_messages = SpanMessages {
+        first_enter: "span started".into(),
+        close: "span finished".into(),
+        ..SpanMessages::default()
+    };
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_spans(true).with_span_messages(span_messages),
+        enter_twice,
+    )
+    .json();
+
+    assert_eq!(
+        messages(&output),
+        ["span started", "first", "second", "span finished"]
+    );
+}
+
+#[test]
+fn close_event_has_busy_and_idle_time() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_span_events(SpanEvents::CLOSE),
+        || {
+            let span = span!(Level::INFO, "work");
+            span.in_scope(|| thread::sleep(Duration::from_millis(20)));
+            thread::sleep(Duration::from_millis(20));
+        },
+    )
+    .json();
+
+    assert_eq!(output.len(), 1);
+    let close = &output[0];
+    for field in ["elapsed", "busy", "idle"] {
+        assert!(close[field].is_string(), "{} missing from {}", field, close);
+    }
+    assert!(close["busy"].as_str().unwrap().ends_with("ms"), "{}", close);
+    assert!(close["idle"].as_str().unwrap().ends_with("ms"), "{}", close);
+}
+
+#[test]
+fn elapsed_is_busy_plus_idle() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| {
+            layer
+                .with_span_events(SpanEvents::CLOSE)
+                .with_duration_fields(DurationFields::NANOS)
+        },
+        || {
+            let span = span!(Level::INFO, "work");
+            thread::sleep(Duration::from_millis(10));
+            span.in_scope(|| thread::sleep(Duration::from_millis(10)));
+            thread::sleep(Duration::from_millis(10));
+        },
+    )
+    .json();
+
+    let close = &output[0];
+    let nanos = |field: &str| close[field].as_u64().unwrap();
+    assert_eq!(nanos("elapsed_ns"), nanos("busy_ns") + nanos("idle_ns"));
+    assert!(nanos("idle_ns") >= 20_000_000, "{}", close);
+}
+
+#[test]
+fn elapsed_and_idle_are_measured_from_creation() {
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_span_events(SpanEvents::CLOSE),
+        || {
+            let span = span!(Level::INFO, "work");
+            thread::sleep(Duration::from_millis(20));
+            span.in_scope(|| {});
+        },
+    )
+    .json();
+
+    let close = &output[0];
+    let millis = |field: &str| {
+        let text = close[field].as_str().unwrap();
+        text.strip_suffix("ms").unwrap().parse::<f64>().unwrap()
+    };
+    assert!(millis("elapsed") >= 20.0, "{}", close);
+    assert!(millis("idle") >= 20.0, "{}", close);
+}
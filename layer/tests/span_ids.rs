@@ -0,0 +1,105 @@
+mod mock_writer;
+
+use std::collections::HashSet;
+
+use layer::fmt::json::JsonFormatter;
+use tracing::{info, info_span};
+
+use crate::mock_writer::run_layer;
+
+#[test]
+fn ids_describe_the_tree_of_spans() {
+    let output = run_layer(
+        JsonFormatter::new().with_span_ids(true),
+        |layer| layer.with_spans(false),
+        || {
+            let _root = info_span!("get_cat").entered();
+            info!("In root");
+            let _link = info_span!("get_cat_link").entered();
+            let _request = info_span!("request").entered();
+            info!("In request");
+        },
+    )
+    .json();
+
+    let root = &output[0];
+    assert!(root.get("parent_span_id").is_none(), "{}", root);
+    assert_eq!(root["root_span_id"], root["span_id"]);
+
+    let request = &output[1];
+    assert_ne!(request["span_id"], root["span_id"]);
+    assert!(request["parent_span_id"].is_string(), "{}", request);
+    assert_ne!(request["parent_span_id"], root["span_id"]);
+    assert_eq!(request["root_span_id"], root["span_id"]);
+
+    let id = request["span_id"].as_str().unwrap();
+    assert_eq!(id.len(), 16);
+    assert!(id.chars().all(|c| c.is_ascii_hexdigit()), "{}", id);
+}
+
+#[test]
+fn ids_are_not_reused_after_a_span_closes() {
+    let output = run_layer(
+        JsonFormatter::new().with_span_ids(true),
+        |layer| layer.with_spans(false),
+        || {
+            for _ in 0..100 {
+                let _span = info_span!("get_cat").entered();
+                info!("Fetching");
+            }
+        },
+    )
+    .json();
+
+    let ids: HashSet<_> = output
+        .iter()
+        .map(|line| line["span_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids.len(), 100);
+}
+
+#[test]
+fn span_events_have_the_ids_of_their_span() {
+    let output = run_layer(
+        JsonFormatter::new().with_span_ids(true),
+        |layer| layer.with_spans(true),
+        || {
+            let _root = info_span!("get_cat").entered();
+            let _link = info_span!("get_cat_link").entered();
+            info!("Fetching link");
+        },
+    )
+    .json();
+
+    let titles: Vec<_> = output.iter().map(|line| line["title"].as_str()).collect();
+    assert_eq!(
+        titles,
+        ["start", "start", "Fetching link", "end", "end"].map(Some)
+    );
+    let (root, link) = (&output[0], &output[1]);
+
+    for line in [&output[1], &output[2], &output[3]] {
+        assert_eq!(line["span_id"], link["span_id"]);
+        assert_eq!(line["parent_span_id"], root["span_id"]);
+        assert_eq!(line["root_span_id"], root["span_id"]);
+    }
+    assert_eq!(output[4]["span_id"], root["span_id"]);
+    assert!(output[4].get("parent_span_id").is_none());
+}
+
+#[test]
+fn ids_are_off_by_default() {
+    let output = run_layer(
+        JsonFormatter::new().with_span_ids(false),
+        |layer| layer.with_spans(false),
+        || {
+            let _span = info_span!("get_cat").entered();
+            info!("Fetching");
+        },
+    )
+    .json();
+
+    for key in ["span_id", "parent_span_id", "root_span_id"] {
+        assert!(output[0].get(key).is_none(), "{} in {}", key, output[0]);
+    }
+}
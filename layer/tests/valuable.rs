@@ -0,0 +1,257 @@
+// Needs `RUSTFLAGS="--cfg tracing_unstable"` and `--features valuable` to run.
+#![cfg(all(tracing_unstable, feature = "valuable"))]
+
+mod mock_writer;
+
+use std::collections::BTreeMap;
+use std::convert::identity;
+
+use layer::fields::ValueLimits;
+use layer::fmt::json::JsonFormatter;
+use layer::fmt::logfmt::LogfmtFormatter;
+use layer::redaction::{Action, Redaction};
+use serde_json::json;
+use tracing::field::valuable;
+use tracing::{info, info_span};
+use valuable::{Fields, NamedField, NamedValues, StructDef, Structable, Valuable, Visit};
+
+use crate::mock_writer::run_layer;
+
+struct Cat {
+    name: &'static str,
+    lives: u8,
+    toys: Vec<&'static str>,
+    owner: Option<Box<Cat>>,
+}
+
+static CAT_FIELDS: &[NamedField<'static>] = &[
+    NamedField::new("name"),
+    NamedField::new("lives"),
+    NamedField::new("toys"),
+    NamedField::new("owner"),
+];
+
+impl Valuable for Cat {
+    fn as_value(&self) -> valuable::Value<'_> {
+        valuable::Value::Structable(self)
+    }
+
+    fn visit(&self, visit: &mut dyn Visit) {
+        visit.visit_named_fields(&NamedValues::new(
+            CAT_FIELDS,
+            &[
+                self.name.as_value(),
+                self.lives.as_value(),
+                self.toys.as_value(),
+                self.owner.as_value(),
+            ],
+        ));
+    }
+}
+
+impl Structable for Cat {
+    fn definition(&self) -> StructDef<'_> {
+        StructDef::new_static("Cat", Fields::Named(CAT_FIELDS))
+    }
+}
+
+fn cat(name: &'static str, owner: Option<Cat>) -> Cat {
+    Cat {
+        name,
+        lives: 9,
+        toys: vec!["mouse", "string"],
+        owner: owner.map(Box::new),
+    }
+}
+
+#[test]
+fn structs_are_nested_objects() {
+    let output = run_layer(JsonFormatter::new(), identity, || {
+        let cat = cat("olly", None);
+        info!(cat = valuable(&cat), "Adopted");
+    })
+    .json();
+
+    assert_eq!(
+        output[0]["cat"],
+        json!({ "name": "olly", "lives": 9, "toys": ["mouse", "string"], "owner": null })
+    );
+}
+
+#[test]
+fn span_fields_are_nested_objects() {
+    let output = run_layer(JsonFormatter::new(), identity, || {
+        let cat = cat("olly", None);
+        let _span = info_span!("adopting", cat = valuable(&cat)).entered();
+        info!("Adopted");
+    })
+    .json();
+
+    assert_eq!(output[0]["cat"]["toys"], json!(["mouse", "string"]));
+}
+
+#[test]
+fn maps_and_enums() {
+    let output = run_layer(JsonFormatter::new(), identity, || {
+        let counts = BTreeMap::from([("mice", 3), ("birds", 1)]);
+        let ok: Result<u32, &str> = Ok(1);
+        let err: Result<u32, &str> = Err("hissed");
+        let big = u128::MAX;
+        info!(
+            counts = valuable(&counts),
+            ok = valuable(&ok),
+            err = valuable(&err),
+            big = valuable(&big),
+            "Counted"
+        );
+    })
+    .json();
+
+    assert_eq!(output[0]["counts"], json!({ "mice": 3, "birds": 1 }));
+    assert_eq!(output[0]["ok"], json!({ "Ok": 1 }));
+    assert_eq!(output[0]["err"], json!({ "Err": "hissed" }));
+    assert_eq!(output[0]["big"], u128::MAX.to_string());
+}
+
+#[test]
+fn deep_values_are_truncated() {
+    let output = run_layer(JsonFormatter::new(), identity, || {
+        let mut cat = cat("0", None);
+        for name in ["1", "2", "3", "4", "5", "6", "7", "8", "9"] {
+            cat = self::cat(name, Some(cat));
+        }
+        info!(cat = valuable(&cat), "Adopted");
+    })
+    .json();
+
+    let mut owner = &output[0]["cat"];
+    for _ in 0..7 {
+        owner = &owner["owner"];
+    }
+    assert_eq!(owner["name"], "2");
+    assert_eq!(owner["owner"], "...");
+}
+
+#[test]
+fn large_values_are_truncated() {
+    let output = run_layer(JsonFormatter::new(), identity, || {
+        let numbers: Vec<u32> = (0..5000).collect();
+        info!(numbers = valuable(&numbers), "Counted");
+    })
+    .json();
+
+    let numbers = output[0]["numbers"].as_array().unwrap();
+    assert!(numbers.len() < 5000);
+    assert_eq!(numbers.last().unwrap(), "...");
+}
+
+#[test]
+fn depth_limit_can_be_changed() {
+    let limits = ValueLimits {
+        max_depth: 2,
+        ..ValueLimits::default()
+    };
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_value_limits(limits),
+        || {
+            let cat = cat("olly", Some(cat("dolly", Some(cat("molly", None)))));
+            info!(cat = valuable(&cat), "Adopted");
+        },
+    )
+    .json();
+
+    assert_eq!(output[0]["cat"]["owner"]["name"], "dolly");
+    assert_eq!(output[0]["cat"]["owner"]["toys"], "...");
+    assert_eq!(output[0]["cat"]["owner"]["owner"], "...");
+}
+
+#[test]
+fn value_limit_can_be_changed() {
+    let limits = ValueLimits {
+        max_values: 4,
+        ..ValueLimits::default()
+    };
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_value_limits(limits),
+        || {
+            let numbers: Vec<u32> = (0..10).collect();
+            info!(numbers = valuable(&numbers), "Counted");
+        },
+    )
+    .json();
+
+    // The list itself is one of the values.
+    assert_eq!(output[0]["numbers"], json!([0, 1, 2, "..."]));
+}
+
+#[test]
+fn long_strings_are_cut_short() {
+    let limits = ValueLimits {
+        max_string_len: 5,
+        ..ValueLimits::default()
+    };
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_value_limits(limits),
+        || {
+            let names = vec!["olly", "dolly", "mollymolly", "éééé"];
+            info!(names = valuable(&names), "Named");
+        },
+    )
+    .json();
+
+    // Strings are cut at a character boundary, `é` is two bytes.
+    assert_eq!(
+        output[0]["names"],
+        json!(["olly", "dolly", "molly...", "éé..."])
+    );
+
+    let output = run_layer(JsonFormatter::new(), identity, || {
+        let long = "a".repeat(5000);
+        info!(long = valuable(&long), "Long");
+    })
+    .json();
+    assert_eq!(output[0]["long"].as_str().unwrap().len(), 4096 + 3);
+}
+
+#[test]
+fn other_formats_get_the_nested_value() {
+    let output = run_layer(LogfmtFormatter::new(), identity, || {
+        let toys = vec!["mouse", "string"];
+        info!(toys = valuable(&toys), "Playing");
+    })
+    .text();
+
+    assert!(
+        output.contains(r#"toys="[\"mouse\",\"string\"]""#),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn nested_fields_are_redacted() {
+    let redaction = Redaction::new()
+        .name("toys", Action::Drop)
+        .name("lives", Action::redact());
+    let output = run_layer(
+        JsonFormatter::new(),
+        |layer| layer.with_redaction(redaction),
+        || {
+            let cat = cat("olly", Some(cat("dolly", None)));
+            info!(cat = valuable(&cat), "Adopted");
+        },
+    )
+    .json();
+
+    assert_eq!(
+        output[0]["cat"],
+        json!({
+            "name": "olly",
+            "lives": "[REDACTED]",
+            "owner": { "name": "dolly", "lives": "[REDACTED]", "owner": null },
+        })
+    );
+}